@@ -0,0 +1,61 @@
+//! Encoding and decoding throughput.
+//!
+//! The target is at least 50 MB/s for decoding and 100 MB/s for encoding the replay-like input
+//! on a desktop machine, so indexing a few hundred thousand fumens takes seconds rather than
+//! minutes.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use fumen::replay::{Game, Settings};
+use fumen::{Fumen, PieceType};
+
+/// A fumen like an imported replay, with a page for each piece and a quiz comment on each page.
+fn replay(pieces: usize) -> Fumen {
+    use PieceType::*;
+    let mut fumen = Fumen::default();
+    let mut column = 0;
+    while fumen.get_pages().len() < pieces {
+        let queue = [I, T, O, S, Z, L, J].iter().copied().cycle();
+        let mut game = Game::new(Settings::default(), queue);
+        while !game.ended() {
+            game.shift_fully(-1);
+            for _ in 0..column {
+                game.shift(1);
+            }
+            game.hard_drop();
+            column = (column + 3) % 9;
+        }
+        fumen.get_pages_mut().extend(game.fumen().get_pages().iter().cloned());
+    }
+    fumen.get_pages_mut().truncate(pieces);
+    fumen
+}
+
+/// A fumen of commented pages that all show the same field, as used for written guides.
+fn annotated(pages: usize) -> Fumen {
+    let mut fumen = Fumen::default();
+    for i in 0..pages {
+        fumen.add_page();
+        let page = fumen.get_pages_mut().last_mut().unwrap();
+        page.set_comment(Some(format!("step {}: place the T piece — then hold", i)));
+    }
+    fumen
+}
+
+fn codec(c: &mut Criterion) {
+    let inputs = [("replay", replay(1000)), ("annotated", annotated(1000))];
+    for (name, fumen) in &inputs {
+        let data = fumen.encode();
+        let mut group = c.benchmark_group(*name);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function("encode", |b| b.iter(|| fumen.encode()));
+        group.bench_function("decode", |b| b.iter(|| Fumen::decode(&data).unwrap()));
+        group.bench_function("decode_lossless", |b| {
+            b.iter(|| Fumen::decode_lossless(&data).unwrap())
+        });
+        group.bench_function("pages_iter", |b| b.iter(|| Fumen::pages_iter(&data).count()));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, codec);
+criterion_main!(benches);
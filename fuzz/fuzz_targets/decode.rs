@@ -0,0 +1,12 @@
+#![no_main]
+use fumen::Fumen;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    // lossless decoding reproduces any data it accepts
+    if let Ok(fumen) = Fumen::decode_lossless(data) {
+        assert_eq!(fumen.encode(), data);
+        assert_eq!(Ok(fumen), Fumen::decode(data));
+    }
+    let _ = Fumen::pages_iter(data).count();
+});
@@ -0,0 +1,8 @@
+#![no_main]
+use fumen::Fumen;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|fumen: Fumen| {
+    let data = fumen.encode();
+    assert_eq!(Fumen::decode(&data), Ok(fumen));
+});
@@ -0,0 +1,78 @@
+//! `Arbitrary` implementations, for fuzzing.
+//!
+//! Only fumens that fumen can store exactly are generated, so `decode(encode(f)) == f` holds for
+//! all of them: pieces are inside the field and comments fit in fumen's comment length limit.
+
+use ::arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{js_escape, CellColor, Fumen, Page, Piece, PieceType, RotationState};
+
+impl<'a> Arbitrary<'a> for PieceType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(PieceType::from_i64(u.int_in_range(1..=7)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for RotationState {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        use RotationState::*;
+        Ok(*u.choose(&[South, East, North, West])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for CellColor {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        use CellColor::*;
+        Ok(*u.choose(&[Empty, I, L, O, Z, T, J, S, Grey])?)
+    }
+}
+
+impl<'a> Arbitrary<'a> for Piece {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let kind: PieceType = u.arbitrary()?;
+        let rotation = u.arbitrary()?;
+        let offsets = kind.offsets(rotation);
+        let min_x = offsets.iter().map(|&(x, _)| x).min().unwrap();
+        let max_x = offsets.iter().map(|&(x, _)| x).max().unwrap();
+        let min_y = offsets.iter().map(|&(_, y)| y).min().unwrap();
+        let max_y = offsets.iter().map(|&(_, y)| y).max().unwrap();
+        Ok(Piece {
+            kind,
+            rotation,
+            x: u.int_in_range(-min_x..=9 - max_x)? as u32,
+            y: u.int_in_range(-min_y..=22 - max_y)? as u32
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Page {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let comment = match u.arbitrary::<Option<&str>>()? {
+            Some(comment) => {
+                let mut escaped_len = 0;
+                let comment = comment.chars().take_while(|c| {
+                    escaped_len += js_escape(c.encode_utf8(&mut [0; 4])).len();
+                    escaped_len <= 4095
+                });
+                Some(comment.collect())
+            }
+            None => None
+        };
+        Ok(Page {
+            piece: u.arbitrary()?,
+            rise: u.arbitrary()?,
+            mirror: u.arbitrary()?,
+            lock: u.arbitrary()?,
+            guideline_switch: u.arbitrary()?,
+            comment,
+            field: u.arbitrary()?,
+            garbage_row: u.arbitrary()?
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for Fumen {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Fumen { pages: u.arbitrary()?, source: None })
+    }
+}
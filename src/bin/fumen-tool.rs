@@ -0,0 +1,86 @@
+//! Command line tools for working with fumens.
+//!
+//! ```text
+//! fumen-tool diff <old fumen> <new fumen>
+//! fumen-tool schema
+//! fumen-tool typescript
+//! fumen-tool tetrio [--baked] <replay file>
+//! fumen-tool jstris [--baked] <replay file>
+//! ```
+//!
+//! `schema` and `typescript` print the JSON Schema and TypeScript definitions for the structured
+//! representation of a fumen.
+//! `tetrio` prints a fumen for each game in a TETR.IO replay, and needs the `tetrio` feature.
+//! `jstris` prints the fumen for a Jstris replay, and needs the `jstris` feature.
+
+use fumen::Fumen;
+#[cfg(any(feature = "tetrio", feature = "jstris"))]
+use fumen::replay::GarbageDisplay;
+
+const USAGE: &str = "usage:
+    fumen-tool diff <old fumen> <new fumen>
+    fumen-tool schema
+    fumen-tool typescript
+    fumen-tool tetrio [--baked] <replay file>
+    fumen-tool jstris [--baked] <replay file>";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["diff", old, new] => diff(old, new),
+        ["schema"] => {
+            print!("{}", fumen::schema::JSON_SCHEMA);
+            Ok(())
+        }
+        ["typescript"] => {
+            print!("{}", fumen::schema::TYPESCRIPT);
+            Ok(())
+        }
+        #[cfg(feature = "tetrio")]
+        ["tetrio", path] => tetrio(path, GarbageDisplay::Rise),
+        #[cfg(feature = "tetrio")]
+        ["tetrio", "--baked", path] => tetrio(path, GarbageDisplay::Baked),
+        #[cfg(feature = "jstris")]
+        ["jstris", path] => jstris(path, GarbageDisplay::Rise),
+        #[cfg(feature = "jstris")]
+        ["jstris", "--baked", path] => jstris(path, GarbageDisplay::Baked),
+        _ => Err(USAGE.to_owned())
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+fn decode(data: &str) -> Result<Fumen, String> {
+    Fumen::decode(data).map_err(|e| format!("{}: {}", data, e))
+}
+
+fn diff(old: &str, new: &str) -> Result<(), String> {
+    print!("{}", decode(old)?.diff(&decode(new)?));
+    Ok(())
+}
+
+#[cfg(any(feature = "tetrio", feature = "jstris"))]
+fn read(path: &str) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(feature = "tetrio")]
+fn tetrio(path: &str, garbage: GarbageDisplay) -> Result<(), String> {
+    let json = read(path)?;
+    let fumens = Fumen::from_tetrio_replay(&json, garbage)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    for fumen in fumens {
+        println!("{}", fumen.encode());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "jstris")]
+fn jstris(path: &str, garbage: GarbageDisplay) -> Result<(), String> {
+    let fumen = Fumen::from_jstris_replay(&read(path)?, garbage)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    println!("{}", fumen.encode());
+    Ok(())
+}
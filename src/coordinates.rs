@@ -0,0 +1,175 @@
+//! Converting piece positions to and from the coordinates used by other games and tools.
+
+use crate::{Piece, PieceType, RotationState};
+
+/// A way of giving the position of a piece.
+///
+/// Systems based on the bounding box give the top-left cell of the box the piece rotates in,
+/// which is 4 by 4 for I pieces, 2 by 2 for O pieces and 3 by 3 for the rest. Rows above the top
+/// of the field such systems count from have negative coordinates.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum CoordinateSystem {
+    /// SRS true rotation centres, with rows counted up from the bottom of the field. This is how
+    /// [`Piece`] gives positions.
+    TrueRotation,
+    /// solution-finder's, which is the same as [`CoordinateSystem::TrueRotation`].
+    Sfinder,
+    /// The centres fumen stores, with rows counted up from the bottom of the field. These are the
+    /// true rotation centres, apart from some rotations of I, O, S and Z pieces.
+    Fumen,
+    /// The bounding box, with rows counted down from the top of TETR.IO's 40-row board.
+    Tetrio,
+    /// The bounding box, with rows counted down from the top of the 20 visible rows.
+    Jstris,
+    /// The bounding box, with rows counted down from the top of the 20 visible rows.
+    Tetris99
+}
+
+/// Where guideline games spawn pieces, as TETR.IO, Jstris and Tetris 99 do: facing North, in the
+/// middle of the two rows above the 20 visible rows, and left of centre for 3-wide pieces.
+pub fn spawn(kind: PieceType) -> Piece {
+    Piece { kind, rotation: RotationState::North, x: 4, y: 20 }
+}
+
+impl CoordinateSystem {
+    /// The position of `piece` in this system.
+    pub fn position(self, piece: Piece) -> (i32, i32) {
+        let (x, y) = (piece.x as i32, piece.y as i32);
+        match self {
+            CoordinateSystem::TrueRotation | CoordinateSystem::Sfinder => (x, y),
+            CoordinateSystem::Fumen => {
+                let (dx, dy) = fumen_offset(piece.kind, piece.rotation);
+                (x + dx, y + dy)
+            }
+            CoordinateSystem::Tetrio | CoordinateSystem::Jstris | CoordinateSystem::Tetris99 => {
+                let (dx, dy) = box_corner(piece.kind, piece.rotation);
+                (x + dx, self.top() - (y + dy))
+            }
+        }
+    }
+
+    /// The piece at position `(x, y)` in this system.
+    ///
+    /// Returns `None` if the piece's true rotation centre would be left of or below the field.
+    pub fn piece(self, kind: PieceType, rotation: RotationState, x: i32, y: i32) -> Option<Piece> {
+        let (x, y) = match self {
+            CoordinateSystem::TrueRotation | CoordinateSystem::Sfinder => (x, y),
+            CoordinateSystem::Fumen => {
+                let (dx, dy) = fumen_offset(kind, rotation);
+                (x - dx, y - dy)
+            }
+            CoordinateSystem::Tetrio | CoordinateSystem::Jstris | CoordinateSystem::Tetris99 => {
+                let (dx, dy) = box_corner(kind, rotation);
+                (x - dx, self.top() - y - dy)
+            }
+        };
+        if x < 0 || y < 0 {
+            return None;
+        }
+        Some(Piece { kind, rotation, x: x as u32, y: y as u32 })
+    }
+
+    /// Where pieces of type `kind` spawn in this system. See [`spawn`].
+    pub fn spawn(self, kind: PieceType) -> (i32, i32) {
+        self.position(spawn(kind))
+    }
+
+    /// The row, counted up from the bottom of the field, that rows are counted down from.
+    fn top(self) -> i32 {
+        match self {
+            CoordinateSystem::Tetrio => 39,
+            _ => 19
+        }
+    }
+}
+
+/// Offset from the true rotation centre to the centre fumen uses.
+pub(crate) fn fumen_offset(kind: PieceType, rotation: RotationState) -> (i32, i32) {
+    match (kind, rotation) {
+        (PieceType::S, RotationState::East) => (1, 0),
+        (PieceType::Z, RotationState::West) => (-1, 0),
+        (PieceType::O, RotationState::West) => (-1, 1),
+        (PieceType::O, RotationState::South) => (-1, 0),
+        (PieceType::I, RotationState::South) => (-1, 0),
+        (PieceType::S, RotationState::North) => (0, 1),
+        (PieceType::Z, RotationState::North) => (0, 1),
+        (PieceType::O, RotationState::North) => (0, 1),
+        (PieceType::I, RotationState::West) => (0, 1),
+        _ => (0, 0)
+    }
+}
+
+/// Offset from the true rotation centre to the top-left cell of the bounding box, y-up.
+fn box_corner(kind: PieceType, rotation: RotationState) -> (i32, i32) {
+    match (kind, rotation) {
+        (PieceType::I, RotationState::North) => (-1, 1),
+        (PieceType::I, RotationState::East) => (-2, 1),
+        (PieceType::I, RotationState::South) => (-2, 2),
+        (PieceType::I, RotationState::West) => (-1, 2),
+        (PieceType::O, RotationState::North) => (0, 1),
+        (PieceType::O, RotationState::East) => (0, 0),
+        (PieceType::O, RotationState::South) => (-1, 0),
+        (PieceType::O, RotationState::West) => (-1, 1),
+        _ => (-1, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::coordinates::*;
+
+    const SYSTEMS: [CoordinateSystem; 6] = [
+        CoordinateSystem::TrueRotation,
+        CoordinateSystem::Sfinder,
+        CoordinateSystem::Fumen,
+        CoordinateSystem::Tetrio,
+        CoordinateSystem::Jstris,
+        CoordinateSystem::Tetris99
+    ];
+
+    #[test]
+    fn round_trip() {
+        use PieceType::*;
+        let rotations = [
+            RotationState::North, RotationState::East, RotationState::South, RotationState::West
+        ];
+        for &kind in &[I, L, O, Z, T, J, S] {
+            for &rotation in &rotations {
+                let piece = Piece { kind, rotation, x: 4, y: 10 };
+                for &system in &SYSTEMS {
+                    let (x, y) = system.position(piece);
+                    assert_eq!(system.piece(kind, rotation, x, y), Some(piece));
+                }
+
+                // fumen's centres are the ones it encodes
+                let (x, y) = CoordinateSystem::Fumen.position(piece);
+                assert_eq!(piece.fumen_pos() as i32, x + (22 - y) * 10);
+
+                // the bounding box holds the piece
+                let size = match kind {
+                    I => 4,
+                    O => 2,
+                    _ => 3
+                };
+                let (x, y) = CoordinateSystem::Tetrio.position(piece);
+                for &(cx, cy) in &piece.cells() {
+                    assert!((x..x + size).contains(&cx), "{:?}", piece);
+                    assert!((y..y + size).contains(&(39 - cy)), "{:?}", piece);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spawn() {
+        assert_eq!(CoordinateSystem::TrueRotation.spawn(PieceType::T), (4, 20));
+        assert_eq!(CoordinateSystem::Tetrio.spawn(PieceType::T), (3, 18));
+        assert_eq!(CoordinateSystem::Tetrio.spawn(PieceType::I), (3, 18));
+        assert_eq!(CoordinateSystem::Tetrio.spawn(PieceType::O), (4, 18));
+        assert_eq!(CoordinateSystem::Jstris.spawn(PieceType::L), (3, -2));
+        assert_eq!(CoordinateSystem::Fumen.spawn(PieceType::S), (4, 21));
+        let t = CoordinateSystem::Jstris.piece(PieceType::T, RotationState::North, -2, 0);
+        assert_eq!(t, None);
+    }
+}
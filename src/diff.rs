@@ -0,0 +1,454 @@
+use wasm_bindgen::prelude::*;
+use js_sys::Array;
+
+use crate::{CellColor, Fumen, Page, Piece};
+
+/// A cell whose colour differs between two pages.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CellChange {
+    pub x: u32,
+    /// y-up
+    pub y: u32,
+    pub from: CellColor,
+    pub to: CellColor
+}
+
+/// A page flag that differs between two pages.
+#[wasm_bindgen]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Flag {
+    Rise = 0,
+    Mirror = 1,
+    Lock = 2,
+    Guideline = 3
+}
+
+/// The differences between two pages.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct PageDiff {
+    /// Cells that were empty and are now filled.
+    #[wasm_bindgen(skip)]
+    pub added: Vec<CellChange>,
+    /// Cells that were filled and are now empty.
+    #[wasm_bindgen(skip)]
+    pub removed: Vec<CellChange>,
+    /// Cells that are filled in both pages, but with different colours.
+    #[wasm_bindgen(skip)]
+    pub recoloured: Vec<CellChange>,
+    /// Changed cells of the garbage row, with `y` set to 0.
+    #[wasm_bindgen(skip)]
+    pub garbage_row: Vec<CellChange>,
+    /// The old and new piece, if the piece changed.
+    #[wasm_bindgen(skip)]
+    pub piece: Option<(Option<Piece>, Option<Piece>)>,
+    #[wasm_bindgen(skip)]
+    pub flags: Vec<Flag>,
+    /// The old and new comment, if the comment changed.
+    #[wasm_bindgen(skip)]
+    pub comment: Option<(Option<String>, Option<String>)>
+}
+
+/// How a page of one fumen corresponds to a page of another.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum PageChange {
+    Unchanged { old: usize, new: usize },
+    Changed { old: usize, new: usize, diff: PageDiff },
+    Inserted { new: usize },
+    Removed { old: usize }
+}
+
+/// The differences between two fumens, as a list of page changes in page order.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct FumenDiff {
+    #[wasm_bindgen(skip)]
+    pub pages: Vec<PageChange>
+}
+
+#[wasm_bindgen]
+impl Page {
+    /// Compares this page to `other`, which is treated as the newer version.
+    pub fn diff(&self, other: &Page) -> PageDiff {
+        let mut diff = PageDiff::default();
+        for y in 0..23 {
+            for x in 0..10 {
+                let change = CellChange {
+                    x: x as u32,
+                    y: y as u32,
+                    from: self.field[y][x],
+                    to: other.field[y][x]
+                };
+                match (change.from, change.to) {
+                    (from, to) if from == to => {}
+                    (CellColor::Empty, _) => diff.added.push(change),
+                    (_, CellColor::Empty) => diff.removed.push(change),
+                    _ => diff.recoloured.push(change)
+                }
+            }
+        }
+        for x in 0..10 {
+            if self.garbage_row[x] != other.garbage_row[x] {
+                diff.garbage_row.push(CellChange {
+                    x: x as u32,
+                    y: 0,
+                    from: self.garbage_row[x],
+                    to: other.garbage_row[x]
+                });
+            }
+        }
+        if self.piece != other.piece {
+            diff.piece = Some((self.piece, other.piece));
+        }
+        for &(flag, changed) in &[
+            (Flag::Rise, self.rise != other.rise),
+            (Flag::Mirror, self.mirror != other.mirror),
+            (Flag::Lock, self.lock != other.lock),
+            (Flag::Guideline, self.guideline_switch != other.guideline_switch)
+        ] {
+            if changed {
+                diff.flags.push(flag);
+            }
+        }
+        if self.comment != other.comment {
+            diff.comment = Some((self.comment.clone(), other.comment.clone()));
+        }
+        diff
+    }
+}
+
+#[wasm_bindgen]
+impl Fumen {
+    /// Compares this fumen to `other`, which is treated as the newer version.
+    ///
+    /// Pages are aligned by finding the longest sequence of identical pages the two fumens
+    /// have in common. Between those, pages are paired up as changed pages where they are
+    /// similar enough, and the rest are inserted or removed.
+    pub fn diff(&self, other: &Fumen) -> FumenDiff {
+        let old = &self.pages;
+        let new = &other.pages;
+
+        // longest common subsequence, lcs[i][j] being the length for old[i..] and new[j..]
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let mut diff = FumenDiff::default();
+        let (mut i, mut j) = (0, 0);
+        let (mut hunk_old, mut hunk_new) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                diff.push_hunk(self, other, hunk_old..i, hunk_new..j);
+                diff.pages.push(PageChange::Unchanged { old: i, new: j });
+                i += 1;
+                j += 1;
+                hunk_old = i;
+                hunk_new = j;
+            } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+                j += 1;
+            } else {
+                i += 1;
+            }
+        }
+        diff.push_hunk(self, other, hunk_old..i, hunk_new..j);
+        diff
+    }
+}
+
+impl Fumen {
+    /// The field edits of each page, the way the fumen editor shows them.
+    ///
+    /// Each page is compared to the previous page's `next_page`, or an empty page for the first
+    /// page, so the edits are what the page adds to the result of the previous page's piece, line
+    /// clears, rise and mirror. Only the cells of the field and garbage row are compared.
+    pub fn page_edits(&self) -> Vec<PageDiff> {
+        let mut prev = Page::default();
+        self.pages.iter().map(|page| {
+            let diff = prev.diff(page);
+            prev = page.next_page();
+            PageDiff {
+                added: diff.added,
+                removed: diff.removed,
+                recoloured: diff.recoloured,
+                garbage_row: diff.garbage_row,
+                ..PageDiff::default()
+            }
+        }).collect()
+    }
+}
+
+impl FumenDiff {
+    /// Pushes the changes for a run of pages that have no identical counterpart.
+    ///
+    /// The pages are paired up by the smallest total edit distance, where a page that is
+    /// inserted or removed costs `UNPAIRED_COST` and a pair costs the number of differences.
+    fn push_hunk(
+        &mut self,
+        old: &Fumen,
+        new: &Fumen,
+        old_range: std::ops::Range<usize>,
+        new_range: std::ops::Range<usize>
+    ) {
+        const UNPAIRED_COST: usize = 20;
+        let old_pages = &old.pages[old_range.clone()];
+        let new_pages = &new.pages[new_range.clone()];
+
+        // cost[i][j] is the cost of aligning old_pages[i..] with new_pages[j..]
+        let mut cost = vec![vec![0; new_pages.len() + 1]; old_pages.len() + 1];
+        for i in (0..=old_pages.len()).rev() {
+            for j in (0..=new_pages.len()).rev() {
+                cost[i][j] = if i == old_pages.len() {
+                    (new_pages.len() - j) * UNPAIRED_COST
+                } else if j == new_pages.len() {
+                    (old_pages.len() - i) * UNPAIRED_COST
+                } else {
+                    let paired = cost[i + 1][j + 1] +
+                        old_pages[i].diff(&new_pages[j]).distance();
+                    paired
+                        .min(cost[i + 1][j] + UNPAIRED_COST)
+                        .min(cost[i][j + 1] + UNPAIRED_COST)
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old_pages.len() || j < new_pages.len() {
+            if i < old_pages.len() && j < new_pages.len() {
+                let diff = old_pages[i].diff(&new_pages[j]);
+                if cost[i][j] == cost[i + 1][j + 1] + diff.distance() {
+                    self.pages.push(PageChange::Changed {
+                        old: old_range.start + i,
+                        new: new_range.start + j,
+                        diff
+                    });
+                    i += 1;
+                    j += 1;
+                    continue;
+                }
+            }
+            if i < old_pages.len() && cost[i][j] == cost[i + 1][j] + UNPAIRED_COST {
+                self.pages.push(PageChange::Removed { old: old_range.start + i });
+                i += 1;
+            } else {
+                self.pages.push(PageChange::Inserted { new: new_range.start + j });
+                j += 1;
+            }
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PageDiff {
+    /// Whether the pages are identical.
+    #[wasm_bindgen(js_name = "isEmpty")]
+    pub fn is_empty(&self) -> bool {
+        *self == PageDiff::default()
+    }
+    /// The number of differences, counting each changed cell, piece, flag and comment once.
+    pub fn distance(&self) -> usize {
+        self.added.len() + self.removed.len() + self.recoloured.len() + self.garbage_row.len() +
+            self.piece.is_some() as usize + self.flags.len() + self.comment.is_some() as usize
+    }
+    #[wasm_bindgen(getter)]
+    pub fn added(&self) -> Array {
+        cell_changes(&self.added)
+    }
+    #[wasm_bindgen(getter)]
+    pub fn removed(&self) -> Array {
+        cell_changes(&self.removed)
+    }
+    #[wasm_bindgen(getter)]
+    pub fn recoloured(&self) -> Array {
+        cell_changes(&self.recoloured)
+    }
+    #[wasm_bindgen(getter, js_name = "garbageRow")]
+    pub fn garbage_row(&self) -> Array {
+        cell_changes(&self.garbage_row)
+    }
+    #[wasm_bindgen(getter, js_name = "pieceChanged")]
+    pub fn piece_changed(&self) -> bool {
+        self.piece.is_some()
+    }
+    #[wasm_bindgen(getter)]
+    pub fn flags(&self) -> Array {
+        let array = Array::new();
+        for &flag in &self.flags {
+            array.push(&JsValue::from(flag as u8));
+        }
+        array
+    }
+    #[wasm_bindgen(getter, js_name = "commentChanged")]
+    pub fn comment_changed(&self) -> bool {
+        self.comment.is_some()
+    }
+}
+
+#[wasm_bindgen]
+impl FumenDiff {
+    /// The number of page changes.
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.pages.len()
+    }
+    /// The index of the old page of the `i`th change, if there is one and it has one.
+    #[wasm_bindgen(js_name = "oldIndex")]
+    pub fn old_index(&self, i: usize) -> Option<usize> {
+        match *self.pages.get(i)? {
+            PageChange::Unchanged { old, .. } |
+            PageChange::Changed { old, .. } |
+            PageChange::Removed { old } => Some(old),
+            PageChange::Inserted { .. } => None
+        }
+    }
+    /// The index of the new page of the `i`th change, if there is one and it has one.
+    #[wasm_bindgen(js_name = "newIndex")]
+    pub fn new_index(&self, i: usize) -> Option<usize> {
+        match *self.pages.get(i)? {
+            PageChange::Unchanged { new, .. } |
+            PageChange::Changed { new, .. } |
+            PageChange::Inserted { new } => Some(new),
+            PageChange::Removed { .. } => None
+        }
+    }
+    /// The differences between the pages of the `i`th change, if there is one and both pages
+    /// exist.
+    #[wasm_bindgen(js_name = "pageDiff")]
+    pub fn page_diff(&self, i: usize) -> Option<PageDiff> {
+        match *self.pages.get(i)? {
+            PageChange::Unchanged { .. } => Some(PageDiff::default()),
+            PageChange::Changed { ref diff, .. } => Some(diff.clone()),
+            _ => None
+        }
+    }
+}
+
+impl std::fmt::Display for PageDiff {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (name, changes) in &[
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("recoloured", &self.recoloured),
+            ("garbage row", &self.garbage_row)
+        ] {
+            for c in changes.iter() {
+                writeln!(fmt, "  {} ({}, {}): {:?} -> {:?}", name, c.x, c.y, c.from, c.to)?;
+            }
+        }
+        if let Some((from, to)) = self.piece {
+            writeln!(fmt, "  piece: {:?} -> {:?}", from, to)?;
+        }
+        for flag in &self.flags {
+            writeln!(fmt, "  flag: {:?}", flag)?;
+        }
+        if let Some((ref from, ref to)) = self.comment {
+            writeln!(fmt, "  comment: {:?} -> {:?}", from, to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Lists the page changes with 1-based page numbers, one per line, followed by the page
+/// differences.
+impl std::fmt::Display for FumenDiff {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for change in &self.pages {
+            match change {
+                PageChange::Unchanged { old, new } =>
+                    writeln!(fmt, "page {} -> {}: unchanged", old + 1, new + 1)?,
+                PageChange::Changed { old, new, diff } => {
+                    writeln!(fmt, "page {} -> {}: changed", old + 1, new + 1)?;
+                    write!(fmt, "{}", diff)?;
+                }
+                PageChange::Inserted { new } => writeln!(fmt, "page {}: inserted", new + 1)?,
+                PageChange::Removed { old } => writeln!(fmt, "page {}: removed", old + 1)?
+            }
+        }
+        Ok(())
+    }
+}
+
+fn cell_changes(changes: &[CellChange]) -> Array {
+    let array = Array::new();
+    for &change in changes {
+        array.push(&JsValue::from(change));
+    }
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::diff::*;
+
+    #[test]
+    fn page_diff() {
+        let mut old = Page::default();
+        old.field[0][0] = CellColor::Grey;
+        old.field[0][1] = CellColor::T;
+        let mut new = old.clone();
+        new.field[0][0] = CellColor::Empty;
+        new.field[0][1] = CellColor::S;
+        new.field[1][5] = CellColor::I;
+        new.lock = false;
+        new.comment = Some("changed".to_owned());
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, vec![CellChange { x: 5, y: 1, from: CellColor::Empty, to: CellColor::I }]);
+        assert_eq!(diff.removed, vec![CellChange { x: 0, y: 0, from: CellColor::Grey, to: CellColor::Empty }]);
+        assert_eq!(diff.recoloured, vec![CellChange { x: 1, y: 0, from: CellColor::T, to: CellColor::S }]);
+        assert_eq!(diff.piece, None);
+        assert_eq!(diff.flags, vec![Flag::Lock]);
+        assert_eq!(diff.comment, Some((None, Some("changed".to_owned()))));
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn page_edits() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0][0] = CellColor::Grey;
+        fumen.add_page();
+        fumen.pages[1].field[1][0] = CellColor::Grey;
+
+        let edits = fumen.page_edits();
+        assert_eq!(edits.len(), 2);
+        let grey = |y| CellChange { x: 0, y, from: CellColor::Empty, to: CellColor::Grey };
+        assert_eq!(edits[0].added, vec![grey(0)]);
+        assert_eq!(edits[1].added, vec![grey(1)]);
+        assert!(edits[1].removed.is_empty() && edits[1].piece.is_none());
+    }
+
+    #[test]
+    fn fumen_diff() {
+        let mut old = Fumen::default();
+        for i in 0..4 {
+            old.add_page();
+            old.pages[i].comment = Some(i.to_string());
+        }
+        let mut new = old.clone();
+        new.pages.remove(1);
+        new.pages[1].field[0][0] = CellColor::Grey;
+        new.pages.push(Page::default());
+
+        let diff = old.diff(&new);
+        assert!(matches!(diff.pages[0], PageChange::Unchanged { old: 0, new: 0 }));
+        assert!(matches!(diff.pages[1], PageChange::Removed { old: 1 }));
+        assert!(matches!(diff.pages[2], PageChange::Changed { old: 2, new: 1, .. }));
+        assert!(matches!(diff.pages[3], PageChange::Unchanged { old: 3, new: 2 }));
+        assert!(matches!(diff.pages[4], PageChange::Inserted { new: 3 }));
+        assert_eq!(diff.pages.len(), 5);
+
+        assert_eq!((diff.old_index(1), diff.new_index(1)), (Some(1), None));
+        assert_eq!(diff.page_diff(0), Some(PageDiff::default()));
+        assert_eq!((diff.old_index(5), diff.new_index(5)), (None, None));
+        assert_eq!(diff.page_diff(5), None);
+    }
+}
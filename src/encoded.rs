@@ -0,0 +1,49 @@
+//! Serialization of a [`Fumen`] as its encoded string, for use with `#[serde(with = "...")]`.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Puzzle {
+//!     #[serde(with = "fumen::encoded")]
+//!     fumen: Fumen
+//! }
+//! ```
+
+use crate::Fumen;
+
+pub fn serialize<S: serde::Serializer>(fumen: &Fumen, ser: S) -> Result<S::Ok, S::Error> {
+    ser.serialize_str(&fumen.encode())
+}
+
+pub fn deserialize<'de, D: serde::Deserializer<'de>>(de: D) -> Result<Fumen, D::Error> {
+    struct Visitor;
+    impl<'de> serde::de::Visitor<'de> for Visitor {
+        type Value = Fumen;
+        fn expecting(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(fmt, "an encoded fumen string")
+        }
+        fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Fumen, E> {
+            Fumen::decode(s).map_err(E::custom)
+        }
+    }
+    de.deserialize_str(Visitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Puzzle {
+        #[serde(with = "crate::encoded")]
+        fumen: Fumen
+    }
+
+    #[test]
+    fn string_form() {
+        let json = r#"{"fumen":"v115@vhAAgH"}"#;
+        let puzzle: Puzzle = serde_json::from_str(json).unwrap();
+        assert_eq!(puzzle.fumen.get_pages().len(), 1);
+        assert_eq!(serde_json::to_string(&puzzle).unwrap(), json);
+        assert!(serde_json::from_str::<Puzzle>(r#"{"fumen":"nope"}"#).is_err());
+    }
+}
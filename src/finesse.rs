@@ -0,0 +1,275 @@
+//! The fewest key presses needed to place pieces, and checking recorded inputs against them.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::coordinates;
+use crate::srs::{KickTable, Rotation};
+use crate::{CellColor, Fumen, Page, Piece, PieceType, RotationState};
+
+/// A key press.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Input {
+    /// Moves the piece one cell left.
+    Left,
+    /// Moves the piece one cell right.
+    Right,
+    /// Moves the piece left as far as it goes, by holding the key until auto-shift finishes.
+    DasLeft,
+    /// Moves the piece right as far as it goes, by holding the key until auto-shift finishes.
+    DasRight,
+    /// Rotates the piece, trying the kicks of the game's kick table.
+    Rotate(Rotation),
+    /// Moves the piece down as far as it goes, without locking it.
+    SoftDrop,
+    /// Moves the piece down as far as it goes and locks it.
+    HardDrop
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Settings {
+    pub kicks: KickTable,
+    /// Whether the game has a key for half rotations.
+    pub half_rotation: bool,
+    /// Where pieces spawn, as the true rotation centre of a piece facing North.
+    pub spawn_x: u32,
+    pub spawn_y: u32
+}
+
+impl Default for Settings {
+    /// Guideline settings: SRS without half rotations, with pieces spawning where
+    /// [`coordinates::spawn`] says.
+    fn default() -> Self {
+        let spawn = coordinates::spawn(PieceType::T);
+        Settings {
+            kicks: KickTable::Srs,
+            half_rotation: false,
+            spawn_x: spawn.x,
+            spawn_y: spawn.y
+        }
+    }
+}
+
+/// A piece placed with more key presses than needed.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Fault {
+    pub page: usize,
+    /// The keys pressed to place the piece.
+    pub inputs: Vec<Input>,
+    /// One of the shortest ways to place the piece.
+    pub optimal: Vec<Input>
+}
+
+impl Page {
+    /// Finds one of the shortest sequences of key presses that places the page's piece, ending
+    /// with a hard drop.
+    ///
+    /// Returns `None` if the page has no piece, or the piece can't be reached from the spawn
+    /// position, such as when it isn't resting on anything.
+    pub fn finesse(&self, settings: &Settings) -> Option<Vec<Input>> {
+        let target = self.piece?;
+        let field = &self.field[..];
+        let mut target_cells = target.cells();
+        target_cells.sort_unstable();
+        let spawn = Piece {
+            kind: target.kind,
+            rotation: RotationState::North,
+            x: settings.spawn_x,
+            y: settings.spawn_y
+        };
+        if !spawn.fits(field) {
+            return None;
+        }
+
+        let mut inputs = vec![
+            Input::DasLeft,
+            Input::DasRight,
+            Input::Left,
+            Input::Right,
+            Input::Rotate(Rotation::Clockwise),
+            Input::Rotate(Rotation::CounterClockwise),
+            Input::SoftDrop
+        ];
+        if settings.half_rotation {
+            inputs.push(Input::Rotate(Rotation::Half));
+        }
+
+        // every input costs one key press, so the first way found to each position is shortest
+        let mut previous: HashMap<Piece, (Piece, Input)> = HashMap::new();
+        let mut queue = VecDeque::from(vec![spawn]);
+        while let Some(piece) = queue.pop_front() {
+            let mut cells = piece.dropped(field).cells();
+            cells.sort_unstable();
+            if cells == target_cells {
+                let mut result = vec![Input::HardDrop];
+                let mut piece = piece;
+                while let Some(&(before, input)) = previous.get(&piece) {
+                    result.push(input);
+                    piece = before;
+                }
+                result.reverse();
+                return Some(result);
+            }
+
+            for &input in &inputs {
+                if let Some(next) = input.apply(piece, field, settings.kicks) {
+                    if next != spawn && !previous.contains_key(&next) {
+                        previous.insert(next, (piece, input));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl Fumen {
+    /// Finds the shortest key presses placing the piece of each page. See [`Page::finesse`].
+    pub fn finesse(&self, settings: &Settings) -> Vec<Option<Vec<Input>>> {
+        self.pages.iter().map(|page| page.finesse(settings)).collect()
+    }
+
+    /// Compares recorded key presses with the fewest needed, listing the pieces that took more.
+    ///
+    /// `inputs` is split into pieces at each hard drop, which are matched in turn to the pages
+    /// that place a piece, which are those with a piece that lock. Pieces whose page's piece
+    /// can't be reached are skipped.
+    pub fn finesse_faults(&self, inputs: &[Input], settings: &Settings) -> Vec<Fault> {
+        let pieces = inputs.split_inclusive(|&input| input == Input::HardDrop);
+        let pages = self.pages.iter().enumerate()
+            .filter(|(_, page)| page.piece.is_some() && page.lock);
+        let mut faults = vec![];
+        for (inputs, (i, page)) in pieces.zip(pages) {
+            if let Some(optimal) = page.finesse(settings) {
+                if inputs.len() > optimal.len() {
+                    faults.push(Fault { page: i, inputs: inputs.to_vec(), optimal });
+                }
+            }
+        }
+        faults
+    }
+}
+
+impl Input {
+    /// The piece after pressing the key, or `None` if it doesn't move.
+    fn apply(self, piece: Piece, field: &[[CellColor; 10]], kicks: KickTable) -> Option<Piece> {
+        let fully = |dx| {
+            let mut moved = piece.shifted(dx, 0, field)?;
+            while let Some(next) = moved.shifted(dx, 0, field) {
+                moved = next;
+            }
+            Some(moved)
+        };
+        match self {
+            Input::Left => piece.shifted(-1, 0, field),
+            Input::Right => piece.shifted(1, 0, field),
+            Input::DasLeft => fully(-1),
+            Input::DasRight => fully(1),
+            Input::Rotate(rotation) => piece.rotated(rotation, field, kicks),
+            Input::SoftDrop => Some(piece.dropped(field)).filter(|&dropped| dropped != piece),
+            Input::HardDrop => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::finesse::*;
+
+    #[test]
+    fn finesse() {
+        let settings = Settings::default();
+        let t = Page {
+            piece: Some(Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 0 }),
+            ..Page::default()
+        };
+        assert_eq!(t.finesse(&settings), Some(vec![Input::HardDrop]));
+        let o = Page {
+            piece: Some(Piece { kind: PieceType::O, rotation: RotationState::North, x: 0, y: 0 }),
+            ..Page::default()
+        };
+        assert_eq!(o.finesse(&settings), Some(vec![Input::DasLeft, Input::HardDrop]));
+        let o = Page {
+            piece: Some(Piece { kind: PieceType::O, rotation: RotationState::North, x: 3, y: 0 }),
+            ..Page::default()
+        };
+        assert_eq!(o.finesse(&settings), Some(vec![Input::Left, Input::HardDrop]));
+        let i = Page {
+            piece: Some(Piece { kind: PieceType::I, rotation: RotationState::East, x: 0, y: 2 }),
+            ..Page::default()
+        };
+        let inputs = i.finesse(&settings).unwrap();
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs[1..], [Input::DasLeft, Input::HardDrop]);
+
+        let t = Page {
+            piece: Some(Piece { kind: PieceType::T, rotation: RotationState::South, x: 4, y: 1 }),
+            ..Page::default()
+        };
+        assert_eq!(t.finesse(&settings).unwrap().len(), 3);
+        let settings = Settings { half_rotation: true, ..settings };
+        assert_eq!(
+            t.finesse(&settings),
+            Some(vec![Input::Rotate(Rotation::Half), Input::HardDrop])
+        );
+
+        // floating pieces can't be placed
+        let floating = Page {
+            piece: Some(Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 5 }),
+            ..Page::default()
+        };
+        assert_eq!(floating.finesse(&settings), None);
+        assert_eq!(Page::default().finesse(&settings), None);
+    }
+
+    #[test]
+    fn tuck() {
+        // an O under an overhang has to be soft dropped and slid in
+        let mut page = Page {
+            piece: Some(Piece { kind: PieceType::O, rotation: RotationState::North, x: 0, y: 0 }),
+            ..Page::default()
+        };
+        page.field[2][..2].copy_from_slice(&[CellColor::Grey; 2]);
+        assert_eq!(page.finesse(&Settings::default()), Some(vec![
+            Input::SoftDrop, Input::DasLeft, Input::HardDrop
+        ]));
+    }
+
+    #[test]
+    fn faults() {
+        let mut fumen = Fumen::default();
+        // a piece that is only shown, and isn't placed
+        fumen.add_page();
+        fumen.pages[0].piece = Some(Piece {
+            kind: PieceType::T,
+            rotation: RotationState::North,
+            x: 4,
+            y: 0
+        });
+        fumen.pages[0].lock = false;
+        fumen.add_page();
+        fumen.pages[1].piece = Some(Piece {
+            kind: PieceType::O,
+            rotation: RotationState::North,
+            x: 0,
+            y: 0
+        });
+        fumen.pages[1].lock = true;
+        fumen.add_page();
+        fumen.pages[2].piece = Some(Piece {
+            kind: PieceType::T,
+            rotation: RotationState::North,
+            x: 4,
+            y: 0
+        });
+        let inputs = [Input::Left, Input::Left, Input::Left, Input::Left, Input::HardDrop];
+        let recorded = [&inputs[..], &[Input::HardDrop]].concat();
+        let faults = fumen.finesse_faults(&recorded, &Settings::default());
+        assert_eq!(faults, vec![Fault {
+            page: 1,
+            inputs: inputs.to_vec(),
+            optimal: vec![Input::DasLeft, Input::HardDrop]
+        }]);
+    }
+}
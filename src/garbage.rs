@@ -0,0 +1,180 @@
+//! Generating garbage for downstacking drills, and rising it into a field page by page.
+
+use crate::quiz::Quiz;
+use crate::rng::ParkMiller;
+use crate::{CellColor, Fumen, Page, PieceType};
+
+/// How garbage rows are generated.
+///
+/// Garbage is received in attacks of `attack_rows` rows, which share their hole column the way
+/// the rows of one attack do in most games. Cheese is single-row attacks with a messiness of 1,
+/// and clean garbage is a messiness of 0.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// The number of rows to generate.
+    pub rows: usize,
+    /// The number of rows in each attack, at least 1.
+    pub attack_rows: usize,
+    /// The chance that each attack's hole is in a different column to the last one, from 0 to 1.
+    pub messiness: f64,
+    /// The number of holes in each row, from 1 to 9. Holes after the first are in random
+    /// columns on every row.
+    pub holes: usize
+}
+
+impl Default for Settings {
+    /// Ten rows of cheese.
+    fn default() -> Self {
+        Settings {
+            rows: 10,
+            attack_rows: 1,
+            messiness: 1.0,
+            holes: 1
+        }
+    }
+}
+
+impl Settings {
+    /// Generates garbage rows, in the order they rise into the field.
+    ///
+    /// The same seed always gives the same rows.
+    pub fn generate(&self, seed: u32) -> Vec<[CellColor; 10]> {
+        let mut rng = ParkMiller::new(seed as f64);
+        let holes = self.holes.clamp(1, 9);
+        let mut rows = Vec::with_capacity(self.rows);
+        let mut column = None;
+        for i in 0..self.rows {
+            if i % self.attack_rows.max(1) == 0 {
+                column = match column {
+                    Some(column) if rng.next_float() >= self.messiness => Some(column),
+                    // pick one of the other columns
+                    Some(column) => Some((column + 1 + rng.next_below(9)) % 10),
+                    None => Some(rng.next_below(10))
+                };
+            }
+
+            let mut row = [CellColor::Grey; 10];
+            row[column.unwrap()] = CellColor::Empty;
+            for _ in 1..holes {
+                let filled: Vec<_> = (0..10).filter(|&x| row[x] != CellColor::Empty).collect();
+                row[filled[rng.next_below(filled.len())]] = CellColor::Empty;
+            }
+            rows.push(row);
+        }
+        rows
+    }
+
+    /// A downstacking drill: a page with the garbage rows generated from `seed` already risen
+    /// into an empty field, and a quiz comment giving the start of `queue`. See
+    /// [`Quiz::from_queue`].
+    pub fn drill(
+        &self, seed: u32, queue: impl IntoIterator<Item = PieceType>, previews: usize
+    ) -> Page {
+        let mut page = Page::default();
+        // the first row to rise ends up highest, and rows that don't fit are pushed out
+        for (y, &row) in self.generate(seed).iter().rev().take(23).enumerate() {
+            page.field[y] = row;
+        }
+        page.comment = Some(Quiz::from_queue(queue, previews).to_string());
+        page
+    }
+}
+
+impl Page {
+    /// Rises `rows` into the field from the bottom, one row per page.
+    ///
+    /// Each page uses the `rise` flag and garbage row to push the next row in, starting from this
+    /// page, and the last page shows the field with all of the rows risen. Rows pushed out of the
+    /// top of the field are lost.
+    pub fn rise_garbage(&self, rows: &[[CellColor; 10]]) -> Fumen {
+        let mut pages = Vec::with_capacity(rows.len() + 1);
+        let mut page = self.clone();
+        for &row in rows {
+            page.rise = true;
+            page.garbage_row = row;
+            let next = page.next_page();
+            pages.push(page);
+            page = next;
+        }
+        pages.push(page);
+        Fumen { pages, source: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::garbage::*;
+
+    fn hole_columns(rows: &[[CellColor; 10]]) -> Vec<usize> {
+        rows.iter().map(|row| row.iter().position(|&cell| cell == CellColor::Empty).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn generate() {
+        let settings = Settings::default();
+        let rows = settings.generate(1);
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows, settings.generate(1));
+        assert_ne!(rows, settings.generate(2));
+        let columns = hole_columns(&rows);
+        for pair in columns.windows(2) {
+            assert_ne!(pair[0], pair[1]);
+        }
+
+        let clean = Settings { messiness: 0.0, ..settings }.generate(1);
+        assert!(hole_columns(&clean).iter().all(|&x| x == columns[0]));
+
+        let attacks = Settings { attack_rows: 3, rows: 9, ..settings }.generate(1);
+        let columns = hole_columns(&attacks);
+        for attack in columns.chunks(3) {
+            assert!(attack.iter().all(|&x| x == attack[0]));
+        }
+        assert_ne!(columns[0], columns[3]);
+        assert_ne!(columns[3], columns[6]);
+
+        let rows = Settings { holes: 3, ..settings }.generate(5);
+        for row in rows {
+            assert_eq!(row.iter().filter(|&&cell| cell == CellColor::Empty).count(), 3);
+        }
+    }
+
+    #[test]
+    fn drill() {
+        let settings = Settings::default();
+        let page = settings.drill(4, randomizer::TetrioBag::new(4), 5);
+        let rows = settings.generate(4);
+        let field = page.get_field();
+        assert_eq!(field[0], rows[9]);
+        assert_eq!(field[9], rows[0]);
+        assert_eq!(field[10], [CellColor::Empty; 10]);
+        let quiz = quiz::Quiz::parse(&page.comment().unwrap()).unwrap();
+        let queue: Vec<_> = randomizer::TetrioBag::new(4).take(6).collect();
+        assert_eq!(quiz.current, Some(queue[0]));
+        assert_eq!(quiz.next, &queue[1..]);
+
+        let tall = Settings { rows: 30, ..settings }.drill(4, vec![], 5);
+        assert_eq!(tall.get_field()[22], Settings { rows: 30, ..settings }.generate(4)[7]);
+    }
+
+    #[test]
+    fn rise_garbage() {
+        let mut page = Page::default();
+        page.field[0][0] = CellColor::T;
+        let rows = Settings::default().generate(3);
+        let fumen = page.rise_garbage(&rows);
+        let pages = fumen.get_pages();
+        assert_eq!(pages.len(), 11);
+        assert!(pages[..10].iter().all(|page| page.rise));
+        assert!(!pages[10].rise);
+        for pair in pages.windows(2) {
+            assert_eq!(pair[0].next_page().get_field(), pair[1].get_field());
+        }
+        let field = pages[10].get_field();
+        assert_eq!(field[0], rows[9]);
+        assert_eq!(field[9], rows[0]);
+        assert_eq!(field[10][0], CellColor::T);
+        assert_eq!(Fumen::decode(&fumen.encode()).unwrap(), fumen);
+    }
+}
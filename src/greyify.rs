@@ -0,0 +1,217 @@
+//! Greying out fields, and tracking which piece each cell came from.
+
+use crate::{CellColor, Fumen, Page, Piece};
+
+impl Page {
+    /// Greys out the field and garbage row, except for the cells of the pieces in `except`.
+    ///
+    /// Filled cells under one of the pieces in `except` are given that piece's colour, so pieces
+    /// that were already grey are coloured again. Cells of those pieces outside the field are
+    /// skipped.
+    pub fn greyify(&mut self, except: &[Piece]) {
+        for cell in self.field.iter_mut().chain(Some(&mut self.garbage_row)).flatten() {
+            if *cell != CellColor::Empty {
+                *cell = CellColor::Grey;
+            }
+        }
+        for piece in except {
+            for &(x, y) in &piece.cells() {
+                if !(0..10).contains(&x) || !(0..23).contains(&y) {
+                    continue;
+                }
+                let cell = &mut self.field[y as usize][x as usize];
+                if *cell != CellColor::Empty {
+                    *cell = piece.kind.into();
+                }
+            }
+        }
+    }
+}
+
+impl Fumen {
+    /// Finds which page's piece placed each cell of each page's field.
+    ///
+    /// Cells are followed from the page their piece locked on through line clears, rising
+    /// garbage and mirroring. Cells that are empty, or that were filled some other way, such as
+    /// being drawn into the field, are `None`. Only whether cells are filled is compared, so cells
+    /// are still followed when their colour is changed.
+    pub fn piece_origins(&self) -> Vec<[[Option<usize>; 10]; 23]> {
+        let mut origins: Vec<[[Option<usize>; 10]; 23]> = Vec::with_capacity(self.pages.len());
+        for (i, page) in self.pages.iter().enumerate() {
+            let mut page_origins = match origins.last() {
+                Some(prev) => next_origins(&self.pages[i - 1], i - 1, prev),
+                None => [[None; 10]; 23]
+            };
+            for (origins, row) in page_origins.iter_mut().zip(&page.field) {
+                for (origin, &cell) in origins.iter_mut().zip(row) {
+                    if cell == CellColor::Empty {
+                        *origin = None;
+                    }
+                }
+            }
+            origins.push(page_origins);
+        }
+        origins
+    }
+
+    /// Greys out everything that was in the field before page `from`, on that page and every page
+    /// after it.
+    ///
+    /// Cells placed by the pieces of page `from` and later pages are given the colour of the
+    /// piece that placed them, even if they were grey. Pages before `from` are left as they are.
+    pub fn greyify_history(&mut self, from: usize) {
+        let origins = self.piece_origins();
+        let kinds: Vec<_> = self.pages.iter().map(|page| page.piece.map(|p| p.kind)).collect();
+        for (page, origins) in self.pages.iter_mut().zip(&origins).skip(from) {
+            for (row, origins) in page.field.iter_mut().zip(origins) {
+                for (cell, &origin) in row.iter_mut().zip(origins) {
+                    if *cell == CellColor::Empty {
+                        continue;
+                    }
+                    *cell = match origin.filter(|&i| i >= from).and_then(|i| kinds[i]) {
+                        Some(kind) => kind.into(),
+                        None => CellColor::Grey
+                    };
+                }
+            }
+            for cell in &mut page.garbage_row {
+                if *cell != CellColor::Empty {
+                    *cell = CellColor::Grey;
+                }
+            }
+        }
+    }
+}
+
+/// Follows the cells of page `index` to the next page, in the same way as [`Page::next_page`].
+fn next_origins(
+    page: &Page, index: usize, origins: &[[Option<usize>; 10]; 23]
+) -> [[Option<usize>; 10]; 23] {
+    let mut origins = *origins;
+    let mut filled = page.field.map(|row| row.map(|cell| cell != CellColor::Empty));
+
+    if let Some(piece) = page.piece.filter(|_| page.lock) {
+        for &(x, y) in &piece.cells() {
+            origins[y as usize][x as usize] = Some(index);
+            filled[y as usize][x as usize] = true;
+        }
+    }
+
+    if page.lock {
+        let mut y = 0;
+        for i in 0..23 {
+            if filled[i].contains(&false) {
+                origins[y] = origins[i];
+                y += 1;
+            }
+        }
+        for row in &mut origins[y..] {
+            *row = [None; 10];
+        }
+    }
+
+    if page.rise {
+        origins.copy_within(0..22, 1);
+        origins[0] = [None; 10];
+    }
+
+    if page.mirror {
+        for row in &mut origins {
+            row.reverse();
+        }
+    }
+
+    origins
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// A fumen with a grey row, an I piece on page 0, a T piece on page 1, and page 2 showing
+    /// both.
+    fn fumen() -> Fumen {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0] = [CellColor::Grey; 10];
+        fumen.pages[0].field[0][9] = CellColor::Empty;
+        fumen.pages[0].piece = Some(Piece {
+            kind: PieceType::I,
+            rotation: RotationState::North,
+            x: 1,
+            y: 1
+        });
+        fumen.add_page();
+        fumen.pages[1].piece = Some(Piece {
+            kind: PieceType::T,
+            rotation: RotationState::North,
+            x: 6,
+            y: 1
+        });
+        fumen.add_page();
+        fumen
+    }
+
+    #[test]
+    fn greyify() {
+        let mut page = fumen().pages[2].clone();
+        page.greyify(&[]);
+        assert_eq!(page.field[1][..3], [CellColor::Grey; 3]);
+        assert_eq!(page.field[1][5..8], [CellColor::Grey; 3]);
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 6, y: 1 };
+        page.greyify(&[t]);
+        assert_eq!(page.field[1][..3], [CellColor::Grey; 3]);
+        assert_eq!(page.field[1][5..8], [CellColor::T; 3]);
+        assert_eq!(page.field[2][6], CellColor::T);
+        assert_eq!(page.field[0][0], CellColor::Grey);
+
+        // pieces sticking out of the field only colour the cells inside it
+        let mut page = Page::default();
+        page.field[0] = [CellColor::I; 10];
+        page.field[22] = [CellColor::I; 10];
+        let left = Piece { kind: PieceType::T, rotation: RotationState::West, x: 0, y: 1 };
+        let top = Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 22 };
+        page.greyify(&[left, top]);
+        assert_eq!(page.field[0][..2], [CellColor::T, CellColor::Grey]);
+        assert_eq!(page.field[22][3..6], [CellColor::T; 3]);
+        assert_eq!(page.field[22][6], CellColor::Grey);
+    }
+
+    #[test]
+    fn piece_origins() {
+        let mut fumen = fumen();
+        // a line clear moves the cells above it down
+        fumen.pages[2].piece = Some(Piece {
+            kind: PieceType::I,
+            rotation: RotationState::East,
+            x: 9,
+            y: 2
+        });
+        fumen.add_page();
+        let origins = fumen.piece_origins();
+        assert_eq!(origins[0][1][0], None);
+        assert_eq!(origins[1][1][0], Some(0));
+        assert_eq!(origins[2][2][6], Some(1));
+        assert_eq!(origins[2][0][0], None);
+        assert_eq!(origins[3][0][0], Some(0));
+        assert_eq!(origins[3][1][6], Some(1));
+        assert_eq!(origins[3][0][9], Some(2));
+    }
+
+    #[test]
+    fn greyify_history() {
+        // start from a fully grey fumen, so the colours have to be recovered
+        let mut fumen = fumen().map_colors(|_| CellColor::Grey);
+        fumen.greyify_history(1);
+        let field = fumen.pages[2].get_field();
+        assert_eq!(field[1][..3], [CellColor::Grey; 3]);
+        assert_eq!(field[1][5..8], [CellColor::T; 3]);
+        assert_eq!(field[2][6], CellColor::T);
+        assert_eq!(field[0][0], CellColor::Grey);
+
+        // recovering the colours of every page
+        fumen.greyify_history(0);
+        assert_eq!(fumen.pages[2].get_field()[1][..3], [CellColor::I; 3]);
+        assert_eq!(fumen.pages[2].get_field()[0][0], CellColor::Grey);
+    }
+}
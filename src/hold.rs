@@ -0,0 +1,209 @@
+//! Checking that the pieces of a fumen can be played from a queue, using hold.
+
+use std::collections::HashSet;
+
+use crate::quiz::Quiz;
+use crate::{Fumen, PieceType};
+
+/// What is done with the current piece to place a page's piece.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum HoldAction {
+    /// The current piece is placed.
+    Place,
+    /// The current piece is held, and the piece that comes out of hold is placed, or the next
+    /// piece in the queue if hold was empty.
+    Hold
+}
+
+/// A state of the queue while placing pieces: how many pieces have been placed, the index of
+/// the current piece in the queue, and the held piece.
+type State = (usize, usize, Option<PieceType>);
+
+impl Fumen {
+    /// Finds how to play the pieces placed by this fumen from the pieces in `quiz`, holding
+    /// pieces where needed.
+    ///
+    /// Pages place their piece if it is set and the page locks. The result has an action for
+    /// each page that places a piece, and `None` for the others. When the current piece is
+    /// empty, the first piece of the queue is the current piece, and once the queue runs out,
+    /// the held piece can still be placed by holding. Placing the current piece is preferred
+    /// over holding when both work.
+    pub fn hold_actions(&self, quiz: &Quiz) -> Result<Vec<Option<HoldAction>>, HoldError> {
+        let placed: Vec<_> = self.pages.iter().enumerate()
+            .filter_map(|(i, page)| page.piece.filter(|_| page.lock).map(|piece| (i, piece.kind)))
+            .collect();
+        let kinds: Vec<_> = placed.iter().map(|&(_, kind)| kind).collect();
+        let queue: Vec<_> = quiz.current.into_iter().chain(quiz.next.iter().copied()).collect();
+
+        let mut search = Search {
+            kinds: &kinds,
+            queue: &queue,
+            failed: HashSet::new(),
+            actions: vec![],
+            furthest: 0
+        };
+        if !search.search((0, 0, quiz.hold)) {
+            return Err(HoldError { page: placed[search.furthest].0 });
+        }
+        let mut actions = vec![None; self.pages.len()];
+        for (&(page, _), &action) in placed.iter().zip(&search.actions) {
+            actions[page] = Some(action);
+        }
+        Ok(actions)
+    }
+
+    /// Lists every order of the pieces in `bag` that this fumen's pieces can't be played from,
+    /// starting with an empty hold. See [`Fumen::hold_actions`].
+    ///
+    /// Orders that are the same because `bag` has repeated pieces are only listed once.
+    pub fn failing_queues(&self, bag: &[PieceType]) -> Vec<Vec<PieceType>> {
+        let mut bag = bag.to_vec();
+        bag.sort_by_key(|&kind| kind as u8);
+        let mut failing = vec![];
+        permutations(&mut bag, 0, &mut |queue| {
+            let quiz = Quiz { hold: None, current: None, next: queue.to_vec() };
+            if self.hold_actions(&quiz).is_err() {
+                failing.push(queue.to_vec());
+            }
+        });
+        failing
+    }
+}
+
+struct Search<'a> {
+    kinds: &'a [PieceType],
+    queue: &'a [PieceType],
+    /// States the rest of the pieces can't be placed from.
+    failed: HashSet<State>,
+    actions: Vec<HoldAction>,
+    /// The most pieces placed in any attempt.
+    furthest: usize
+}
+
+impl Search<'_> {
+    fn search(&mut self, state: State) -> bool {
+        let (placed, index, hold) = state;
+        self.furthest = self.furthest.max(placed);
+        if placed == self.kinds.len() {
+            return true;
+        }
+        if self.failed.contains(&state) {
+            return false;
+        }
+
+        let kind = self.kinds[placed];
+        let current = self.queue.get(index).copied();
+        if current == Some(kind) && self.attempt(HoldAction::Place, (placed + 1, index + 1, hold)) {
+            return true;
+        }
+        let held = match (hold, current) {
+            (Some(held), _) => {
+                Some((held, (placed + 1, index + current.is_some() as usize, current)))
+            }
+            (None, Some(_)) => {
+                self.queue.get(index + 1).map(|&next| (next, (placed + 1, index + 2, current)))
+            }
+            (None, None) => None
+        };
+        if let Some((_, next)) = held.filter(|&(held, _)| held == kind) {
+            if self.attempt(HoldAction::Hold, next) {
+                return true;
+            }
+        }
+
+        self.failed.insert(state);
+        false
+    }
+
+    fn attempt(&mut self, action: HoldAction, state: State) -> bool {
+        self.actions.push(action);
+        if self.search(state) {
+            return true;
+        }
+        self.actions.pop();
+        false
+    }
+}
+
+/// Calls `f` with each distinct order of `pieces`, which must be sorted from `start` onwards.
+fn permutations(pieces: &mut [PieceType], start: usize, f: &mut impl FnMut(&[PieceType])) {
+    if start == pieces.len() {
+        f(pieces);
+        return;
+    }
+    for i in start..pieces.len() {
+        if pieces[start..i].contains(&pieces[i]) {
+            continue;
+        }
+        pieces[start..=i].rotate_right(1);
+        permutations(pieces, start + 1, f);
+        pieces[start..=i].rotate_left(1);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct HoldError {
+    /// The first page whose piece can't be placed in any way.
+    pub page: usize
+}
+
+impl std::fmt::Display for HoldError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "the piece on page {} can't be played from the queue", self.page + 1)
+    }
+}
+
+impl std::error::Error for HoldError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::hold::*;
+    use crate::quiz::Quiz;
+
+    #[test]
+    fn hold_actions() {
+        use PieceType::*;
+        // a T, an I and an O placed side by side on the floor
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].piece = Some(Piece { kind: T, rotation: RotationState::North, x: 1, y: 0 });
+        fumen.add_page();
+        fumen.pages[1].piece = Some(Piece { kind: I, rotation: RotationState::North, x: 5, y: 0 });
+        fumen.add_page();
+        fumen.pages[2].piece = Some(Piece { kind: O, rotation: RotationState::North, x: 8, y: 0 });
+        fumen.add_page();
+        let quiz = Quiz::parse("#Q=[](I)TO").unwrap();
+        assert_eq!(fumen.hold_actions(&quiz), Ok(vec![
+            Some(HoldAction::Hold), Some(HoldAction::Hold), Some(HoldAction::Hold), None
+        ]));
+        let quiz = Quiz::parse("#Q=[](T)OI").unwrap();
+        assert_eq!(fumen.hold_actions(&quiz), Ok(vec![
+            Some(HoldAction::Place), Some(HoldAction::Hold), Some(HoldAction::Hold), None
+        ]));
+        let quiz = Quiz::parse("#Q=[O](T)I").unwrap();
+        assert_eq!(fumen.hold_actions(&quiz), Ok(vec![
+            Some(HoldAction::Place), Some(HoldAction::Place), Some(HoldAction::Hold), None
+        ]));
+        let quiz = Quiz::parse("#Q=[](I)OT").unwrap();
+        assert_eq!(fumen.hold_actions(&quiz), Err(HoldError { page: 0 }));
+        let quiz = Quiz::parse("#Q=[](T)II").unwrap();
+        assert_eq!(fumen.hold_actions(&quiz), Err(HoldError { page: 2 }));
+    }
+
+    #[test]
+    fn failing_queues() {
+        use PieceType::*;
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].piece = Some(Piece { kind: T, rotation: RotationState::North, x: 1, y: 0 });
+        fumen.add_page();
+        fumen.pages[1].piece = Some(Piece { kind: I, rotation: RotationState::North, x: 5, y: 0 });
+        fumen.add_page();
+        fumen.pages[2].piece = Some(Piece { kind: O, rotation: RotationState::North, x: 8, y: 0 });
+        assert_eq!(fumen.failing_queues(&[O, T, I]), vec![vec![I, O, T], vec![O, I, T]]);
+        // the second T gets in the way of holding for the I
+        assert!(fumen.failing_queues(&[T, T, I, O]).contains(&vec![T, T, O, I]));
+        assert_eq!(fumen.failing_queues(&[T, T, I, O]).len(), 5);
+    }
+}
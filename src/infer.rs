@@ -0,0 +1,330 @@
+//! Inferring piece placements from fields drawn cell by cell.
+
+use crate::tiling::{KINDS, ROTATIONS};
+use crate::{CellColor, Fumen, Page, Piece, PieceType};
+
+/// Finds pieces that were placed to turn the field `before` into the field `after`.
+///
+/// The cells filled in `after` but empty in `before` have to split into pieces whose colour
+/// matches their cells. Returns `None` if they don't, or if any other cell differs between the
+/// two fields, such as when lines were cleared or grey cells were added. The pieces are sorted
+/// from the bottom of the field up, so each piece can rest on the ones before it.
+///
+/// When the cells split into pieces in more than one way, one of the ways is chosen. Pieces with
+/// the same cells in more than one rotation are given the first rotation out of North, East,
+/// South and West.
+pub fn find_placements(
+    before: &[[CellColor; 10]; 23], after: &[[CellColor; 10]; 23]
+) -> Option<Vec<Piece>> {
+    let mut added = [[CellColor::Empty; 10]; 23];
+    for y in 0..23 {
+        for x in 0..10 {
+            match (before[y][x], after[y][x]) {
+                (old, new) if old == new => {}
+                (CellColor::Empty, new) if new != CellColor::Grey => added[y][x] = new,
+                _ => return None
+            }
+        }
+    }
+    split_pieces(&added)
+}
+
+impl Page {
+    /// Finds pieces that make up exactly the cells of the field that have a piece's colour.
+    ///
+    /// Grey cells are left out. See [`find_placements`] for how the pieces are chosen and
+    /// ordered.
+    pub fn find_pieces(&self) -> Option<Vec<Piece>> {
+        let mut coloured = self.field;
+        for cell in coloured.iter_mut().flatten() {
+            if *cell == CellColor::Grey {
+                *cell = CellColor::Empty;
+            }
+        }
+        split_pieces(&coloured)
+    }
+}
+
+impl Fumen {
+    /// Rewrites pages drawn cell by cell into pages that place pieces.
+    ///
+    /// Each page without a piece, followed by a page whose field only adds pieces to its own, is
+    /// replaced by a page for each of those pieces, each page showing the field the piece is
+    /// placed on. The page after them gets the field the last piece leaves, so lines the pieces
+    /// fill are cleared, and its own piece moves down with the rows it was drawn on. A fumen of a
+    /// single page without a piece is rewritten to build the coloured cells of its field,
+    /// starting from the grey cells, with a last page showing the finished field. Pages that rise
+    /// or mirror are left alone.
+    pub fn infer_pieces(&mut self) {
+        let old = std::mem::take(&mut self.pages);
+        if let [page] = &old[..] {
+            if page.piece.is_none() && !page.rise && !page.mirror {
+                if let Some(pieces) = page.find_pieces().filter(|pieces| !pieces.is_empty()) {
+                    let mut start = page.clone();
+                    for row in &mut start.field {
+                        for cell in row {
+                            if *cell != CellColor::Grey {
+                                *cell = CellColor::Empty;
+                            }
+                        }
+                    }
+                    push_placements(&mut self.pages, start, &pieces);
+                    let last = self.pages.last().unwrap().next_page();
+                    self.pages.push(last);
+                    return;
+                }
+            }
+        }
+
+        // the page after the last inferred piece, which the next drawn page has to follow
+        let mut follow: Option<Page> = None;
+        for (i, page) in old.iter().enumerate() {
+            let page = match follow.take() {
+                Some(follow) => following_page(page, follow),
+                None => page.clone()
+            };
+            if page.piece.is_none() && !page.rise && !page.mirror {
+                let placements = old.get(i + 1)
+                    .and_then(|next| find_placements(&page.field, &next.field))
+                    .filter(|pieces| !pieces.is_empty());
+                if let Some(pieces) = placements {
+                    push_placements(&mut self.pages, page, &pieces);
+                    follow = Some(self.pages.last().unwrap().next_page());
+                    continue;
+                }
+            }
+            self.pages.push(page);
+        }
+    }
+}
+
+/// The drawn page `drawn` with the field of `follow`, the page after the pieces inferred to build
+/// it, and its piece moved down past the full lines of the drawn field, which `follow` cleared.
+fn following_page(drawn: &Page, follow: Page) -> Page {
+    let piece = drawn.piece.map(|piece| {
+        let cleared = drawn.field[..piece.y as usize].iter()
+            .filter(|row| !row.contains(&CellColor::Empty))
+            .count();
+        Piece { y: piece.y - cleared as u32, ..piece }
+    });
+    Page { piece, field: follow.field, garbage_row: follow.garbage_row, ..drawn.clone() }
+}
+
+/// Splits the coloured cells of `field` into pieces of their colour, sorted from the bottom up.
+fn split_pieces(field: &[[CellColor; 10]; 23]) -> Option<Vec<Piece>> {
+    let mut pieces = vec![];
+    for &kind in &KINDS {
+        let color = CellColor::from(kind);
+        let mut cells = field.map(|row| row.map(|cell| cell == color));
+        if cells.iter().flatten().filter(|&&cell| cell).count() % 4 != 0 {
+            return None;
+        }
+        if !split_kind(&mut cells, kind, &mut pieces) {
+            return None;
+        }
+    }
+    pieces.sort_by_key(|piece| {
+        let cells = piece.cells();
+        let y = cells.iter().map(|&(_, y)| y).min().unwrap();
+        let x = cells.iter().map(|&(x, _)| x).min().unwrap();
+        (y, x)
+    });
+    Some(pieces)
+}
+
+/// Covers the cells set in `cells` with pieces of type `kind`, clearing the cells it covers.
+///
+/// The lowest, leftmost cell has to be covered by some piece, so each way of covering it is tried
+/// in turn, backtracking when the rest of the cells can't be covered.
+fn split_kind(cells: &mut [[bool; 10]; 23], kind: PieceType, pieces: &mut Vec<Piece>) -> bool {
+    let first = (0..23)
+        .flat_map(|y| (0..10).map(move |x| (x, y)))
+        .find(|&(x, y)| cells[y][x]);
+    let (x, y) = match first {
+        Some((x, y)) => (x as i32, y as i32),
+        None => return true
+    };
+
+    for &rotation in &ROTATIONS {
+        for &(dx, dy) in &kind.offsets(rotation) {
+            let (px, py) = (x - dx, y - dy);
+            if px < 0 || py < 0 {
+                continue;
+            }
+            let piece = Piece { kind, rotation, x: px as u32, y: py as u32 };
+            if !piece.in_field() {
+                continue;
+            }
+            let piece_cells = piece.cells();
+            if !piece_cells.iter().all(|&(x, y)| cells[y as usize][x as usize]) {
+                continue;
+            }
+
+            for &(x, y) in &piece_cells {
+                cells[y as usize][x as usize] = false;
+            }
+            pieces.push(piece);
+            if split_kind(cells, kind, pieces) {
+                return true;
+            }
+            pieces.pop();
+            for &(x, y) in &piece_cells {
+                cells[y as usize][x as usize] = true;
+            }
+        }
+    }
+    false
+}
+
+/// Pushes a page for each of `pieces` in turn, starting from `page`.
+///
+/// The pieces are positioned in `page`'s field, so they are moved down past any lines cleared by
+/// the pieces before them.
+pub(crate) fn push_placements(pages: &mut Vec<Page>, mut page: Page, pieces: &[Piece]) {
+    // the row of `page`'s field each row of the current field came from
+    let mut rows: Vec<Option<usize>> = (0..23).map(Some).collect();
+    for &piece in pieces {
+        // pieces never cover a cleared line, since the line was full before they were placed
+        let y = rows.iter().position(|&row| row == Some(piece.y as usize)).unwrap();
+        let piece = Piece { y: y as u32, ..piece };
+        page.piece = Some(piece);
+        page.lock = true;
+
+        let mut field = page.field;
+        for &(x, y) in &piece.cells() {
+            field[y as usize][x as usize] = piece.kind.into();
+        }
+        let mut y = 0;
+        for (i, row) in field.iter().enumerate() {
+            if row.contains(&CellColor::Empty) {
+                rows[y] = rows[i];
+                y += 1;
+            }
+        }
+        for row in &mut rows[y..] {
+            *row = None;
+        }
+
+        let next = page.next_page();
+        pages.push(page);
+        page = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::infer::*;
+
+    fn place(field: &mut [[CellColor; 10]; 23], piece: Piece) {
+        for &(x, y) in &piece.cells() {
+            field[y as usize][x as usize] = piece.kind.into();
+        }
+    }
+
+    #[test]
+    fn find_pieces() {
+        let mut page = Page::default();
+        page.field[0] = [CellColor::Grey; 10];
+        page.field[0][0] = CellColor::Empty;
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 1 };
+        let s = Piece { kind: PieceType::S, rotation: RotationState::East, x: 0, y: 2 };
+        place(&mut page.field, t);
+        place(&mut page.field, s);
+        assert_eq!(page.find_pieces(), Some(vec![s, t]));
+
+        // two pieces of the same colour next to each other
+        let mut page = Page::default();
+        let o1 = Piece { kind: PieceType::O, rotation: RotationState::North, x: 0, y: 0 };
+        let o2 = Piece { kind: PieceType::O, rotation: RotationState::North, x: 2, y: 0 };
+        place(&mut page.field, o1);
+        place(&mut page.field, o2);
+        assert_eq!(page.find_pieces(), Some(vec![o1, o2]));
+
+        page.field[5][5] = CellColor::O;
+        assert_eq!(page.find_pieces(), None);
+        page.field[5][5] = CellColor::L;
+        assert_eq!(page.find_pieces(), None);
+    }
+
+    #[test]
+    fn placements() {
+        let mut before = [[CellColor::Empty; 10]; 23];
+        before[0] = [CellColor::Grey; 10];
+        before[0][9] = CellColor::Empty;
+        let mut after = before;
+        let i = Piece { kind: PieceType::I, rotation: RotationState::East, x: 9, y: 2 };
+        place(&mut after, i);
+        assert_eq!(find_placements(&before, &after), Some(vec![i]));
+        assert_eq!(find_placements(&before, &before), Some(vec![]));
+        // cells removed
+        assert_eq!(find_placements(&after, &before), None);
+        // grey cells added
+        after[5][5] = CellColor::Grey;
+        assert_eq!(find_placements(&before, &after), None);
+    }
+
+    #[test]
+    fn infer_pieces() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0] = [CellColor::Grey; 10];
+        fumen.pages[0].field[0][..4].copy_from_slice(&[CellColor::Empty; 4]);
+        fumen.pages[0].comment = Some("drawn".to_owned());
+        fumen.add_page();
+        // an I piece filling the line, drawn before the line is cleared, with a T piece on it
+        let i = Piece { kind: PieceType::I, rotation: RotationState::North, x: 1, y: 0 };
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 1, y: 1 };
+        place(&mut fumen.pages[1].field, i);
+        place(&mut fumen.pages[1].field, t);
+        // the drawn page's own piece rests on the line that is cleared
+        let o = Piece { kind: PieceType::O, rotation: RotationState::North, x: 5, y: 1 };
+        fumen.pages[1].piece = Some(o);
+        fumen.pages[1].comment = Some("built".to_owned());
+
+        fumen.infer_pieces();
+        assert_eq!(fumen.pages.len(), 3);
+        assert_eq!(fumen.pages[0].piece, Some(i));
+        assert_eq!(fumen.pages[0].comment(), Some("drawn".to_owned()));
+        assert_eq!(fumen.pages[1].piece, Some(Piece {
+            kind: PieceType::T,
+            rotation: RotationState::North,
+            x: 1,
+            y: 0
+        }));
+        assert_eq!(fumen.pages[1].field, [[CellColor::Empty; 10]; 23]);
+        // the full line is cleared from the drawn page, which moves its piece down with it
+        assert_eq!(fumen.pages[2].piece, Some(Piece { y: 0, ..o }));
+        assert_eq!(fumen.pages[2].comment(), Some("built".to_owned()));
+        assert_eq!(fumen.pages[2].field, fumen.pages[1].next_page().field);
+        assert_eq!(fumen.pages[2].field[0][..3], [CellColor::T; 3]);
+        assert_eq!(fumen.pages[2].field[0][3], CellColor::Empty);
+        assert!(fumen.check_continuity().is_empty());
+    }
+
+    #[test]
+    fn infer_single_page() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0] = [CellColor::Grey; 10];
+        fumen.pages[0].field[0][0] = CellColor::Empty;
+        let i = Piece { kind: PieceType::I, rotation: RotationState::East, x: 0, y: 2 };
+        let o = Piece { kind: PieceType::O, rotation: RotationState::North, x: 1, y: 1 };
+        place(&mut fumen.pages[0].field, i);
+        place(&mut fumen.pages[0].field, o);
+        let field = fumen.pages[0].field;
+
+        fumen.infer_pieces();
+        assert_eq!(fumen.pages.len(), 3);
+        assert_eq!(fumen.pages[0].piece, Some(i));
+        assert_eq!(fumen.pages[0].field[1], [CellColor::Empty; 10]);
+        assert_eq!(fumen.pages[1].piece, Some(Piece {
+            kind: PieceType::O,
+            rotation: RotationState::North,
+            x: 1,
+            y: 0
+        }));
+        assert!(fumen.check_continuity().is_empty());
+        assert_eq!(fumen.pages[2].field[..22], field[1..]);
+    }
+}
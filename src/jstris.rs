@@ -0,0 +1,214 @@
+//! Import of Jstris replays.
+//!
+//! A replay is a JSON object with the game's settings in `c` and its actions in `d`. The actions
+//! are a bit stream written 6 bits to a character in URL-safe base64, most significant bit first,
+//! where each action is a 12-bit time in milliseconds followed by a 4-bit action code. Garbage
+//! actions are followed by the number of lines in 5 bits and the hole's column in 4 bits, and an
+//! auxiliary action followed by a 4-bit zero adds 4096 milliseconds to the times that follow.
+//!
+//! Jstris records gravity and auto-shift as actions of their own, so unlike TETR.IO replays
+//! there is no timing to simulate and the times are not used. Only the standard SRS rotation
+//! system and the 7-bag randomizer are supported.
+
+use serde_json::Value;
+
+use crate::randomizer::JstrisBag;
+use crate::replay::{Game, GarbageDisplay, ParseReplayError, Settings};
+use crate::srs::{KickTable, Rotation};
+use crate::Fumen;
+
+const MOVE_LEFT: u32 = 0;
+const MOVE_RIGHT: u32 = 1;
+const DAS_LEFT: u32 = 2;
+const DAS_RIGHT: u32 = 3;
+const ROTATE_LEFT: u32 = 4;
+const ROTATE_RIGHT: u32 = 5;
+const ROTATE_180: u32 = 6;
+const HARD_DROP: u32 = 7;
+const SOFT_DROP_BEGIN_END: u32 = 8;
+const GRAVITY_STEP: u32 = 9;
+const HOLD_BLOCK: u32 = 10;
+const GARBAGE_ADD: u32 = 11;
+const SGARBAGE_ADD: u32 = 12;
+const REDBAR_SET: u32 = 13;
+const ARR_MOVE: u32 = 14;
+const AUX: u32 = 15;
+
+const AUX_TIME_OFFSET: u32 = 0;
+
+impl Fumen {
+    /// Plays a Jstris replay, with a page for each piece placed.
+    ///
+    /// Each page's comment is a quiz comment with the hold and queue when the piece spawned,
+    /// so the pages can be used as quiz input.
+    pub fn from_jstris_replay(
+        json: &str, garbage: GarbageDisplay
+    ) -> Result<Fumen, ParseReplayError> {
+        let replay: Value = serde_json::from_str(json).map_err(|_| ParseReplayError)?;
+        let config = &replay["c"];
+        let seed = match &config["seed"] {
+            Value::String(seed) => seed.clone(),
+            Value::Number(seed) => seed.to_string(),
+            _ => return Err(ParseReplayError)
+        };
+        let standard = |key: &str| config[key].as_u64().unwrap_or(0) == 0;
+        if !standard("r") || !standard("bs") {
+            return Err(ParseReplayError);
+        }
+        let instant_soft_drop = standard("sd");
+        let mut actions = Bits::new(replay["d"].as_str().ok_or(ParseReplayError)?);
+
+        let settings = Settings { kicks: KickTable::Srs, previews: 5, garbage };
+        let mut game = Game::new(settings, JstrisBag::new(&seed));
+        let mut soft_drop = false;
+        while !game.ended() {
+            let action = match actions.read(12).and_then(|_| actions.read(4)) {
+                Some(action) => action,
+                None => break
+            };
+            match action {
+                MOVE_LEFT => {
+                    game.shift(-1);
+                }
+                MOVE_RIGHT => {
+                    game.shift(1);
+                }
+                DAS_LEFT => {
+                    game.shift_fully(-1);
+                }
+                DAS_RIGHT => {
+                    game.shift_fully(1);
+                }
+                ROTATE_LEFT => {
+                    game.rotate(Rotation::CounterClockwise);
+                }
+                ROTATE_RIGHT => {
+                    game.rotate(Rotation::Clockwise);
+                }
+                ROTATE_180 => {
+                    game.rotate(Rotation::Half);
+                }
+                HARD_DROP => game.hard_drop(),
+                SOFT_DROP_BEGIN_END => soft_drop = !soft_drop,
+                GRAVITY_STEP => {
+                    game.fall(1);
+                }
+                HOLD_BLOCK => {
+                    game.hold();
+                }
+                GARBAGE_ADD => {
+                    let lines = actions.read(5).ok_or(ParseReplayError)?;
+                    let column = actions.read(4).ok_or(ParseReplayError)?;
+                    game.add_garbage(lines as usize, column as usize);
+                }
+                SGARBAGE_ADD => {
+                    let lines = actions.read(5).ok_or(ParseReplayError)?;
+                    game.add_garbage(lines as usize, 10);
+                }
+                REDBAR_SET => {
+                    actions.read(5).ok_or(ParseReplayError)?;
+                }
+                ARR_MOVE => {
+                    let right = actions.read(1).ok_or(ParseReplayError)?;
+                    game.shift(if right == 1 { 1 } else { -1 });
+                }
+                AUX => match actions.read(4) {
+                    Some(AUX_TIME_OFFSET) => {}
+                    _ => return Err(ParseReplayError)
+                },
+                _ => unreachable!()
+            }
+            if soft_drop && instant_soft_drop {
+                game.sonic_drop();
+            }
+        }
+        Ok(game.fumen())
+    }
+}
+
+/// Reads a bit stream from URL-safe base64.
+struct Bits<'a> {
+    chars: std::str::Chars<'a>,
+    buffer: u32,
+    len: u32
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a str) -> Bits<'a> {
+        Bits { chars: data.chars(), buffer: 0, len: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        while self.len < bits {
+            let value = match self.chars.next()? {
+                c @ 'A' ..= 'Z' => c as u32 - 'A' as u32,
+                c @ 'a' ..= 'z' => c as u32 - 'a' as u32 + 26,
+                c @ '0' ..= '9' => c as u32 - '0' as u32 + 52,
+                '-' => 62,
+                '_' => 63,
+                _ => return None
+            };
+            self.buffer = self.buffer << 6 | value;
+            self.len += 6;
+        }
+        self.len -= bits;
+        let value = self.buffer >> self.len;
+        self.buffer &= (1 << self.len) - 1;
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::jstris::*;
+    use crate::quiz::Quiz;
+
+    /// Writes actions as `(action, extra bits)` pairs, with a time of 0.
+    fn encode(actions: &[(u32, &[(u32, u32)])]) -> String {
+        let chars = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let mut bits = vec![];
+        for &(action, extra) in actions {
+            let fields = [(0, 12), (action, 4)];
+            for &(value, len) in fields.iter().chain(extra) {
+                bits.extend((0..len).rev().map(|i| value >> i & 1));
+            }
+        }
+        bits.chunks(6).map(|chunk| {
+            let value = (0..6).fold(0, |v, i| v << 1 | chunk.get(i).unwrap_or(&0));
+            chars[value as usize] as char
+        }).collect()
+    }
+
+    #[test]
+    fn replay() {
+        let data = encode(&[
+            (DAS_LEFT, &[]),
+            (HARD_DROP, &[]),
+            (GARBAGE_ADD, &[(2, 5), (9, 4)]),
+            (HOLD_BLOCK, &[]),
+            (ROTATE_RIGHT, &[]),
+            (DAS_RIGHT, &[]),
+            (HARD_DROP, &[])
+        ]);
+        let json = format!(r#"{{"c": {{"v": 3, "seed": "abc", "r": 0}}, "d": "{}"}}"#, data);
+        let fumen = Fumen::from_jstris_replay(&json, GarbageDisplay::Baked).unwrap();
+        let pages = fumen.get_pages();
+        assert_eq!(pages.len(), 2);
+
+        let queue: Vec<_> = JstrisBag::new("abc").take(7).collect();
+        let first = pages[0].piece.unwrap();
+        assert_eq!(first.kind, queue[0]);
+        assert!(first.cells().iter().any(|&(x, _)| x == 0));
+        let second = pages[1].piece.unwrap();
+        assert_eq!(second.kind, queue[2]);
+        assert_eq!(second.rotation, RotationState::East);
+        assert!(second.cells().iter().any(|&(x, _)| x == 9));
+        let quiz = Quiz::parse(&pages[1].comment().unwrap()).unwrap();
+        assert_eq!(quiz.current, Some(queue[1]));
+        assert_eq!(quiz.next, &queue[2..7]);
+
+        let json = format!(r#"{{"c": {{"seed": "abc", "r": 1}}, "d": "{}"}}"#, data);
+        assert_eq!(Fumen::from_jstris_replay(&json, GarbageDisplay::Baked), Err(ParseReplayError));
+    }
+}
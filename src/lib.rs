@@ -1,27 +1,72 @@
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsError;
 use js_sys::Array;
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+pub mod coordinates;
+pub mod diff;
+#[cfg(feature = "serde")]
+pub mod encoded;
+pub mod finesse;
+pub mod garbage;
+pub mod greyify;
+pub mod hold;
+pub mod infer;
+#[cfg(feature = "jstris")]
+pub mod jstris;
+pub mod merge;
+mod optimize;
+pub mod order;
+pub mod quiz;
+pub mod randomizer;
+pub mod replay;
+mod rng;
+mod rows;
+pub mod schema;
+pub mod sfinder;
+pub mod srs;
+pub mod stream;
+#[cfg(feature = "tetrio")]
+pub mod tetrio;
+pub mod tetrio_map;
+pub mod tiling;
+pub mod transform;
+
 #[wasm_bindgen]
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Fumen {
     pages: Vec<Page>,
-    pub guideline: bool
+    #[cfg_attr(feature = "serde", serde(skip))]
+    source: Option<Source>
 }
 #[wasm_bindgen]
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct Page {
     pub piece: Option<Piece>,
 
     pub rise: bool,
     pub mirror: bool,
     pub lock: bool,
+    /// Switches between colour and monochrome display, the only change fumen can store.
+    ///
+    /// The first page is shown in colour, or in monochrome if this is set. Later pages are shown
+    /// the same way as the previous page, or in colour if this is set, so once a page is in
+    /// colour the pages after it can't be monochrome. See [`Fumen::guideline_at`].
+    pub guideline_switch: bool,
     comment: Option<String>,
     /// y-up
+    #[cfg_attr(feature = "serde", serde(with = "rows"))]
     field: [[CellColor; 10]; 23],
+    #[cfg_attr(feature = "serde", serde(with = "rows::row"))]
     garbage_row: [CellColor; 10]
 }
 #[wasm_bindgen]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PieceType {
     I = 1,
     L = 2,
@@ -44,9 +89,71 @@ impl PieceType {
             _ => unreachable!()
         }
     }
+
+    /// Parses a piece letter, as used by quiz comments and piece queues.
+    pub fn from_char(c: char) -> Option<PieceType> {
+        Some(match c {
+            'I' => PieceType::I,
+            'L' => PieceType::L,
+            'O' => PieceType::O,
+            'Z' => PieceType::Z,
+            'T' => PieceType::T,
+            'J' => PieceType::J,
+            'S' => PieceType::S,
+            _ => return None
+        })
+    }
+
+    pub fn to_char(self) -> char {
+        match self {
+            PieceType::I => 'I',
+            PieceType::L => 'L',
+            PieceType::O => 'O',
+            PieceType::Z => 'Z',
+            PieceType::T => 'T',
+            PieceType::J => 'J',
+            PieceType::S => 'S'
+        }
+    }
+
+    /// The cells of the piece in the given rotation, relative to its true rotation centre.
+    ///
+    /// The centre is always one of the cells, so one of the offsets is `(0, 0)`.
+    pub fn offsets(self, rotation: RotationState) -> [(i32, i32); 4] {
+        let mut cells = match self {
+            PieceType::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+            PieceType::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            PieceType::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+            PieceType::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+            PieceType::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+            PieceType::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+            PieceType::Z => [(1, 0), (0, 0), (0, 1), (-1, 1)]
+        };
+
+        for (x, y) in &mut cells {
+            match rotation {
+                RotationState::North => {}
+                RotationState::East => {
+                    std::mem::swap(x, y);
+                    *y = -*y;
+                }
+                RotationState::South => {
+                    *x = -*x;
+                    *y = -*y;
+                }
+                RotationState::West => {
+                    std::mem::swap(x, y);
+                    *x = -*x;
+                }
+            }
+        }
+
+        cells
+    }
 }
 #[wasm_bindgen]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RotationState {
     South = 0,
     East = 1,
@@ -58,6 +165,8 @@ pub enum RotationState {
 /// Represents a tetromino piece using true rotation.
 #[wasm_bindgen]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "rows::PieceFields"))]
 pub struct Piece {
     pub kind: PieceType,
     pub rotation: RotationState,
@@ -67,6 +176,7 @@ pub struct Piece {
 }
 #[wasm_bindgen]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CellColor {
     Empty = 0,
     I = 1,
@@ -88,94 +198,101 @@ const BASE64_CHARS: [u8; 64] = [
     b'y', b'z', b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7',
     b'8', b'9', b'+', b'/'
 ];
+const INVALID_BASE64: u8 = 0xFF;
+/// The value of each base64 character, indexed by byte.
+const BASE64_VALUES: [u8; 256] = {
+    let mut values = [INVALID_BASE64; 256];
+    let mut i = 0;
+    while i < 64 {
+        values[BASE64_CHARS[i] as usize] = i as u8;
+        i += 1;
+    }
+    values
+};
 #[wasm_bindgen]
 impl Fumen {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Fumen {
         Fumen {
             pages: vec![],
-            guideline: true
+            source: None
         }
     }
     /// Encode as a fumen data string.
+    ///
+    /// If the fumen was decoded with `decode_lossless`, the original data is reused for the parts
+    /// of it that have not been changed.
     pub fn encode(&self) -> String {
-        // we need a vec and not a string here since we need to go back and patch in the
-        // length of empty field sequences... and i don't want to do 2-pass encoding
-        let mut data = b"v115@".to_vec();
+        // the length of empty field sequences is patched in once the sequence ends, so it
+        // doesn't need 2-pass encoding
+        let mut data = "v115@".to_owned();
+        let patch = |data: &mut String, index: usize, count: usize| {
+            let c = BASE64_CHARS[count] as char;
+            data.replace_range(index..index + 1, c.encode_utf8(&mut [0; 4]));
+        };
         let mut prev_field = [[CellColor::Empty; 10]; 24];
         let mut empty_field = None;
+        let mut source_run = 0;
         let mut first = true;
-        for page in &self.pages {
+        for (i, page) in self.pages.iter().enumerate() {
+            let source = self.source.as_ref().and_then(|s| s.pages.get(i));
+
             // encode field
             let deltas = fumen_field_delta(prev_field, (*page).fumen_field());
-            if deltas == [[8; 10]; 24] {
+            if source_run > 0 {
+                // this page is part of an unchanged field sequence copied from the source
+                source_run -= 1;
+            } else if let Some(source) = source.filter(
+                |s| self.source_field_intact(i, s, prev_field)
+            ) {
+                // finalize the empty field sequence
+                if let Some((index, count)) = empty_field {
+                    patch(&mut data, index, count);
+                    empty_field = None;
+                }
+                data.push_str(&source.field);
+                source_run = source.run;
+            } else if deltas == [[8; 10]; 24] {
                 // do special-case unchanged field stuff
-                if let Some((ref mut index, ref mut count)) = empty_field {
+                if let Some((index, ref mut count)) = empty_field {
                     // count empty fields
                     *count += 1;
                     if *count == 63 {
-                        data[*index] = BASE64_CHARS[*count];
+                        patch(&mut data, index, *count);
                         empty_field = None;
                     }
                 } else {
                     // new empty field encoding
-                    data.push(b'v');
-                    data.push(b'h');
+                    data.push_str("vh");
                     empty_field = Some((data.len(), 0));
-                    data.push(0);
+                    data.push('A');
                 }
             } else {
                 // finalize the empty field sequence
                 if let Some((index, count)) = empty_field {
-                    data[index] = BASE64_CHARS[count];
+                    patch(&mut data, index, count);
                     empty_field = None;
                 }
-                // do run-length encoding of deltas
-                let mut prev = deltas[0][0];
-                let mut count = 0;
-                for y in 0..24 {
-                    for x in 0..10 {
-                        if deltas[y][x] == prev {
-                            count += 1;
-                        } else {
-                            let num = prev * 240 + count - 1;
-                            data.push(BASE64_CHARS[num & 0x3F]);
-                            data.push(BASE64_CHARS[num >> 6 & 0x3F]);
-                            prev = deltas[y][x];
-                            count = 1;
-                        }
-                    }
-                }
-                let num = prev * 240 + count - 1;
-                data.push(BASE64_CHARS[num & 0x3F]);
-                data.push(BASE64_CHARS[num >> 6 & 0x3F]);
-            }
-
-            let page_flags = (*page).fumen_number() as usize + if first {
-                first = false;
-                self.guideline as usize * 240 * 128
-            } else { 0 };
-            data.push(BASE64_CHARS[page_flags & 0x3F]);
-            data.push(BASE64_CHARS[page_flags >> 6 & 0x3F]);
-            data.push(BASE64_CHARS[page_flags >> 12 & 0x3F]);
-
-            if let Some(ref comment) = (*page).comment {
-                let mut encoded = js_escape(comment);
-                encoded.truncate(4095);
-                data.push(BASE64_CHARS[encoded.len() & 0x3F]);
-                data.push(BASE64_CHARS[encoded.len() >> 6 & 0x3F]);
-
-                for c in encoded.chunks(4) {
-                    let mut v = 0;
-                    for &c in c.iter().rev() {
-                        v *= 96;
-                        v += c as usize - 0x20;
-                    }
-                    for _ in 0..5 {
-                        data.push(BASE64_CHARS[v & 0x3F]);
-                        v >>= 6;
-                    }
-                }
+                push_field(&mut data, &deltas);
+            }
+
+            let source_number = source.filter(|s| {
+                s.page.piece == page.piece && s.page.rise == page.rise &&
+                    s.page.mirror == page.mirror && s.page.lock == page.lock &&
+                    s.page.guideline_switch == page.guideline_switch &&
+                    s.page.comment.is_some() == page.comment.is_some()
+            });
+            if let Some(source) = source_number {
+                data.push_str(&source.number);
+            } else {
+                push_page_number(&mut data, page, first);
+            }
+            first = false;
+
+            if let Some(source) = source.filter(|s| s.page.comment == page.comment) {
+                data.push_str(&source.comment);
+            } else if let Some(ref comment) = (*page).comment {
+                push_comment(&mut data, comment);
             }
 
             // this handles piece locking, line clear, mirror, and rise rules
@@ -184,141 +301,57 @@ impl Fumen {
 
         // finalize the empty field sequence
         if let Some((index, count)) = empty_field {
-            data[index] = BASE64_CHARS[count];
+            patch(&mut data, index, count);
         }
 
-        String::from_utf8(data).unwrap()
+        if let Some(ref source) = self.source {
+            data.push_str(&source.tail);
+        }
+
+        data
     }
 
     /// Decodes a fumen data string.
     /// 
     pub fn decode(data: &str) -> Result<Fumen, DecodeFumenError> {
-        unsafe { 
-            Fumen::decode_opt(data).ok_or(DecodeFumenError)
-        }
-        
+        Fumen::decode_opt(data, false).ok_or(DecodeFumenError)
     }
 
-    unsafe fn decode_opt(data: &str) -> Option<Fumen> {
-        if data.chars().take(5).collect::<String>() != "v115@" {
-            return None;
-        }
-        let mut iter = data[5..].chars().filter(|&c| c != '?').map(from_base64).peekable();
-        let mut fumen = Fumen::default();
-        let mut empty_fields = 0;
-        while iter.peek().is_some() {
-            let page = fumen.add_page();
-            if empty_fields == 0 {
-                // decode field spec
-                let mut delta = [[0; 10]; 24];
-                let mut x = 0;
-                let mut y = 0;
-                while y != 24 {
-                    let number = iter.next()?? + 64 * iter.next()??;
-                    let value = number / 240;
-                    let repeats = number % 240 + 1;
-                    for _ in 0..repeats {
-                        if y == 24 {
-                            return None;
-                        }
-                        delta[y][x] = value;
-                        x += 1;
-                        if x == 10 {
-                            y += 1;
-                            x = 0;
-                        }
-                    }
-                }
-                if delta == [[8; 10]; 24] {
-                    empty_fields = iter.next()??;
-                }
-                for y in 0..23 {
-                    for x in 0..10 {
-                        let value = delta[y][x] + (*page).field[22-y][x] as usize - 8;
-                        (*page).field[22-y][x] = decode_cell_color(value)?;
-                    }
-                }
-                for x in 0..10 {
-                    let value = delta[23][x] + (*page).garbage_row[x] as usize - 8;
-                    (*page).garbage_row[x] = decode_cell_color(value)?;
-                }
-            } else {
-                empty_fields -= 1;
-            }
-
-            // decode page data
-            let number = iter.next()?? + iter.next()?? * 64 + iter.next()?? * 64*64;
-            let piece_type = number % 8;
-            let piece_rot = number / 8 % 4;
-            let piece_pos = number / 32 % 240;
-
-            (*page).piece = if piece_type == 0 { None } else {
-                let kind = match piece_type {
-                    1 => PieceType::I,
-                    2 => PieceType::L,
-                    3 => PieceType::O,
-                    4 => PieceType::Z,
-                    5 => PieceType::T,
-                    6 => PieceType::J,
-                    7 => PieceType::S,
-                    _ => unreachable!()
-                };
-                let rotation = match piece_rot {
-                    0 => RotationState::South,
-                    1 => RotationState::East,
-                    2 => RotationState::North,
-                    3 => RotationState::West,
-                    _ => unreachable!()
-                };
-                let x = piece_pos as u32 % 10;
-                let y = 22 - piece_pos as u32 / 10;
-                Some(Piece {
-                    kind, rotation,
-                    // we need to convert fumen centers to SRS true rotation centers
-                    x: match (kind, rotation) {
-                        (PieceType::S, RotationState::East) => x - 1,
-                        (PieceType::Z, RotationState::West) => x + 1,
-                        (PieceType::O, RotationState::West) => x + 1,
-                        (PieceType::O, RotationState::South) => x + 1,
-                        (PieceType::I, RotationState::South) => x + 1,
-                        _ => x
-                    },
-                    y: match (kind, rotation) {
-                        (PieceType::S, RotationState::North) => y - 1,
-                        (PieceType::Z, RotationState::North) => y - 1,
-                        (PieceType::O, RotationState::North) => y - 1,
-                        (PieceType::O, RotationState::West) => y - 1,
-                        (PieceType::I, RotationState::West) => y - 1,
-                        _ => y
-                    }
-                })
-            };
-
-            let flags = number / 32 / 240;
-            (*page).rise = flags & 0b1 != 0;
-            (*page).mirror = flags & 0b10 != 0;
-            let guideline = flags & 0b100 != 0;
-            let comment = flags & 0b1000 != 0;
-            (*page).lock = flags & 0b10000 == 0;
-
-            if comment {
-                let mut length = iter.next()?? + iter.next()?? * 64;
-                let mut escaped = String::new();
-                while length > 0 {
-                    let mut number = iter.next()?? + iter.next()?? * 64 + iter.next()?? * 64 * 64
-                        + iter.next()?? * 64 * 64 * 64 + iter.next()?? * 64 * 64 * 64 * 64;
-                    for _ in 0..length.min(4) {
-                        escaped.push(std::char::from_u32(number as u32 % 96 + 0x20)?);
-                        length -= 1;
-                        number /= 96;
-                    }
-                }
-                (*page).comment = Some(js_unescape(&escaped));
-            }
+    /// Decodes a fumen data string, keeping every bit of it.
+    ///
+    /// The resulting fumen remembers the data it was decoded from, including flag bits and
+    /// encoding choices that are not otherwise represented, and `encode` reuses that data for
+    /// every page that has not been changed since. This means `encode` reproduces the original
+    /// string exactly unless the fumen is edited.
+    #[wasm_bindgen(js_name = "decodeLossless")]
+    pub fn decode_lossless(data: &str) -> Result<Fumen, DecodeFumenError> {
+        Fumen::decode_opt(data, true).ok_or(DecodeFumenError)
+    }
 
-            if fumen.pages.len() == 1 {
-                fumen.guideline = guideline;
+    fn decode_opt(data: &str, lossless: bool) -> Option<Fumen> {
+        let mut decoder = Decoder::new(data)?;
+        let mut fumen = Fumen::default();
+        let mut source = Source::default();
+        while decoder.has_more() {
+            let prev_field = decoder.prev_field();
+            let field_start = decoder.reader.pos;
+            let decoded = decoder.next_page()?;
+            if lossless {
+                let data = decoder.reader.data;
+                source.pages.push(SourcePage {
+                    page: decoded.page.clone(),
+                    prev_field,
+                    field: data[field_start..decoded.number_start].to_owned(),
+                    run: decoded.run,
+                    number: data[decoded.number_start..decoded.comment_start].to_owned(),
+                    comment: data[decoded.comment_start..decoder.reader.pos].to_owned()
+                });
             }
+            fumen.pages.push(decoded.page);
+        }
+        if lossless {
+            source.tail = decoder.reader.data[decoder.reader.pos..].to_owned();
+            fumen.source = Some(source);
         }
         Some(fumen)
     }
@@ -334,13 +367,35 @@ impl Fumen {
         });
         self.pages.last_mut().unwrap()
     }
+    /// Whether the first page is shown in colour.
     #[wasm_bindgen(getter)]
-    pub fn pages(&self) -> Array {
-        let array = Array::new();
-        for pg in self.pages.iter() {
-            array.push(&JsValue::from(pg.clone()));
+    pub fn guideline(&self) -> bool {
+        self.guideline_at(0)
+    }
+    /// Sets whether the first page is shown in colour.
+    ///
+    /// Later pages that don't switch to colour are shown the same way. The flag is stored on the
+    /// first page, so this returns `false` without changing anything on a fumen with no pages.
+    #[wasm_bindgen(js_name = "setGuideline")]
+    pub fn set_guideline(&mut self, guideline: bool) -> bool {
+        match self.pages.first_mut() {
+            Some(page) => {
+                page.guideline_switch = !guideline;
+                true
+            }
+            None => false
         }
-        array
+    }
+    /// Whether the page at `index` is shown in colour.
+    #[wasm_bindgen(js_name = "guidelineAt")]
+    pub fn guideline_at(&self, index: usize) -> bool {
+        let mut guideline = true;
+        for (i, page) in self.pages.iter().take(index + 1).enumerate() {
+            if page.guideline_switch {
+                guideline = i != 0;
+            }
+        }
+        guideline
     }
 }
 impl Fumen {
@@ -350,20 +405,340 @@ impl Fumen {
     pub fn get_pages_mut(&mut self) -> &mut Vec<Page> {
         &mut self.pages
     }
+
+    /// Lists the pages whose field is not the natural follow-on of the previous page.
+    ///
+    /// Fumen stores every page as a set of edits on top of the previous page's `next_page`, so
+    /// these pages still encode, but the edits are part of the page rather than the result of
+    /// the previous page's piece, line clears, rise or mirror.
+    pub fn check_continuity(&self) -> Vec<usize> {
+        let mut result = vec![];
+        for (i, pair) in self.pages.windows(2).enumerate() {
+            let expected = pair[0].next_page();
+            if expected.field != pair[1].field || expected.garbage_row != pair[1].garbage_row {
+                result.push(i + 1);
+            }
+        }
+        result
+    }
+
+    /// Rewrites the comments the way the fumen editor shows them.
+    ///
+    /// Comments are truncated to what fits in the format, and pages without a comment are given
+    /// the comment shown on them, which is the previous page's. Fields are left as they are,
+    /// since fumen stores every field exactly. The original data kept by `decode_lossless` is
+    /// kept too, so the parts of pages that are unchanged still encode the way they were.
+    pub fn normalize(&mut self) {
+        let mut normalized = Fumen::decode(&self.encode()).expect("encoded fumen failed to decode");
+        normalized.source = self.source.take();
+        *self = normalized;
+        let mut comment = None;
+        for page in &mut self.pages {
+            if page.comment.is_some() {
+                comment = page.comment.clone();
+            } else {
+                page.comment = comment.clone();
+            }
+        }
+    }
+}
+
+/// The data string a fumen was decoded from, split up by page.
+#[derive(Clone, Debug, Default)]
+struct Source {
+    pages: Vec<SourcePage>,
+    /// trailing `?` separators
+    tail: String
+}
+
+#[derive(Clone, Debug)]
+struct SourcePage {
+    /// the page as it was decoded
+    page: Page,
+    /// the field the page's field was encoded against
+    prev_field: [[CellColor; 10]; 24],
+    /// empty if the page is part of an unchanged field sequence started by an earlier page
+    field: String,
+    /// the number of pages after this one that are part of its unchanged field sequence
+    run: usize,
+    number: String,
+    comment: String
+}
+
+impl Fumen {
+    /// Whether the field data of the source page can be copied for the page at `index`.
+    fn source_field_intact(
+        &self, index: usize, source: &SourcePage, prev_field: [[CellColor; 10]; 24]
+    ) -> bool {
+        let page = &self.pages[index];
+        if source.field.is_empty() || source.prev_field != prev_field ||
+                source.page.field != page.field || source.page.garbage_row != page.garbage_row {
+            return false;
+        }
+        // the unchanged field sequence is copied with it, so the pages it covers must still be
+        // unchanged
+        let mut prev = page;
+        for next in self.pages[index + 1..].iter().take(source.run) {
+            if prev.next_page().fumen_field() != next.fumen_field() {
+                return false;
+            }
+            prev = next;
+        }
+        true
+    }
+}
+
+/// Reads base64 values from fumen data, skipping the `?` separators.
+///
+/// Yields `None` for characters that are not base64.
+struct Reader<'a> {
+    data: &'a str,
+    pos: usize
+}
+
+impl Reader<'_> {
+    fn has_more(&self) -> bool {
+        self.data.as_bytes()[self.pos..].iter().any(|&c| c != b'?')
+    }
+
+    /// Reads a number written as `digits` base64 digits, least significant first.
+    fn read(&mut self, digits: usize) -> Option<usize> {
+        let mut value = 0;
+        for i in 0..digits {
+            value |= self.next()?? << (6 * i);
+        }
+        Some(value)
+    }
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Option<usize>;
+    fn next(&mut self) -> Option<Option<usize>> {
+        let data = self.data.as_bytes();
+        while let Some(&c) = data.get(self.pos) {
+            self.pos += 1;
+            if c != b'?' {
+                return Some(match BASE64_VALUES[c as usize] {
+                    INVALID_BASE64 => None,
+                    value => Some(value as usize)
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Decodes fumen data one page at a time.
+struct Decoder<'a> {
+    reader: Reader<'a>,
+    /// the last page decoded with fumen's page rules applied, which the next page starts from
+    base: Option<Page>,
+    /// the number of pages left in the current unchanged field sequence
+    empty_fields: usize
+}
+
+struct DecodedPage {
+    page: Page,
+    /// the number of later pages in the unchanged field sequence this page starts
+    run: usize,
+    number_start: usize,
+    comment_start: usize
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a str) -> Option<Decoder<'a>> {
+        if data.chars().take(5).collect::<String>() != "v115@" {
+            return None;
+        }
+        Some(Decoder {
+            reader: Reader { data: &data[5..], pos: 0 },
+            base: None,
+            empty_fields: 0
+        })
+    }
+
+    fn has_more(&self) -> bool {
+        self.reader.has_more()
+    }
+
+    fn prev_field(&self) -> [[CellColor; 10]; 24] {
+        match self.base {
+            Some(ref p) => p.fumen_field(),
+            None => [[CellColor::Empty; 10]; 24]
+        }
+    }
+
+    /// Decodes the next page, returning `None` if the data is invalid.
+    fn next_page(&mut self) -> Option<DecodedPage> {
+        let iter = &mut self.reader;
+        let first = self.base.is_none();
+        let mut page = self.base.take().unwrap_or_default();
+        let mut run = 0;
+        if self.empty_fields == 0 {
+            // decode field spec, applying each run of deltas as it is read
+            let mut unchanged = true;
+            let mut cell = 0;
+            while cell != 240 {
+                let number = iter.read(2)?;
+                let value = number / 240;
+                let repeats = number % 240 + 1;
+                if cell + repeats > 240 {
+                    return None;
+                }
+                unchanged &= value == 8;
+                if value != 8 {
+                    for cell in cell..cell + repeats {
+                        let y = cell / 10;
+                        let row = match y {
+                            23 => &mut page.garbage_row,
+                            _ => &mut page.field[22 - y]
+                        };
+                        let color = (value + row[cell % 10] as usize).checked_sub(8);
+                        row[cell % 10] = color.and_then(decode_cell_color)?;
+                    }
+                }
+                cell += repeats;
+            }
+            if unchanged {
+                self.empty_fields = iter.read(1)?;
+                run = self.empty_fields;
+            }
+        } else {
+            self.empty_fields -= 1;
+        }
+        let number_start = iter.pos;
+
+        // decode page data
+        let number = iter.read(3)?;
+        let piece_type = number % 8;
+        let piece_rot = number / 8 % 4;
+        let piece_pos = number / 32 % 240;
+
+        page.piece = if piece_type == 0 { None } else {
+            let kind = match piece_type {
+                1 => PieceType::I,
+                2 => PieceType::L,
+                3 => PieceType::O,
+                4 => PieceType::Z,
+                5 => PieceType::T,
+                6 => PieceType::J,
+                7 => PieceType::S,
+                _ => unreachable!()
+            };
+            let rotation = match piece_rot {
+                0 => RotationState::South,
+                1 => RotationState::East,
+                2 => RotationState::North,
+                3 => RotationState::West,
+                _ => unreachable!()
+            };
+            let x = piece_pos as i32 % 10;
+            let y = 22 - piece_pos as i32 / 10;
+            // we need to convert fumen centers to SRS true rotation centers
+            let (dx, dy) = coordinates::fumen_offset(kind, rotation);
+            let (x, y) = (x - dx, y - dy);
+            // fumen can't show pieces that stick out of the field
+            if x < 0 || y < 0 {
+                return None;
+            }
+            let piece = Piece { kind, rotation, x: x as u32, y: y as u32 };
+            if !piece.in_field() {
+                return None;
+            }
+            Some(piece)
+        };
+
+        let flags = number / 32 / 240;
+        page.rise = flags & 0b1 != 0;
+        page.mirror = flags & 0b10 != 0;
+        let guideline = flags & 0b100 != 0;
+        let comment = flags & 0b1000 != 0;
+        page.lock = flags & 0b10000 == 0;
+        let comment_start = iter.pos;
+
+        if comment {
+            let mut length = iter.read(2)?;
+            let mut escaped = String::with_capacity(length);
+            while length > 0 {
+                let mut number = iter.read(5)?;
+                for _ in 0..length.min(4) {
+                    escaped.push((number % 96 + 0x20) as u8 as char);
+                    length -= 1;
+                    number /= 96;
+                }
+            }
+            // most comments have nothing escaped, so there is nothing to unescape
+            page.comment = Some(match escaped.contains('%') {
+                true => js_unescape(&escaped),
+                false => escaped
+            });
+        }
+
+        // fumen sets the bit on the first page, and only some editors set it on later pages
+        page.guideline_switch = guideline != first;
+
+        self.base = Some(page.next_page());
+        Some(DecodedPage { page, run, number_start, comment_start })
+    }
 }
 
 fn fumen_field_delta(
     from: [[CellColor; 10]; 24], to: [[CellColor; 10]; 24]
-) -> [[usize; 10]; 24] {
+) -> [[u8; 10]; 24] {
     let mut deltas = [[0; 10]; 24];
-    for y in 0..24 {
-        for x in 0..10 {
-            deltas[y][x] = 8 + to[y][x] as usize - from[y][x] as usize
+    for ((deltas, to), from) in deltas.iter_mut().zip(&to).zip(&from) {
+        for ((delta, &to), &from) in deltas.iter_mut().zip(to).zip(from) {
+            *delta = 8 + to as u8 - from as u8;
         }
     }
     deltas
 }
 
+/// Writes the run-length encoded field deltas of a page.
+fn push_field(data: &mut String, deltas: &[[u8; 10]; 24]) {
+    let mut prev = deltas[0][0];
+    let mut count = 0;
+    for row in deltas {
+        for &delta in row {
+            if delta == prev {
+                count += 1;
+            } else {
+                push_base64(data, prev as usize * 240 + count - 1, 2);
+                prev = delta;
+                count = 1;
+            }
+        }
+    }
+    push_base64(data, prev as usize * 240 + count - 1, 2);
+}
+
+/// Writes the piece and flags of a page.
+fn push_page_number(data: &mut String, page: &Page, first: bool) {
+    let page_flags = page.fumen_number() as usize + page.guideline_bit(first) as usize * 240 * 128;
+    push_base64(data, page_flags, 3);
+}
+
+fn push_comment(data: &mut String, comment: &str) {
+    let mut encoded = js_escape(comment);
+    encoded.truncate(4095);
+    push_base64(data, encoded.len(), 2);
+    for c in encoded.chunks(4) {
+        let mut v = 0;
+        for &c in c.iter().rev() {
+            v *= 96;
+            v += c as usize - 0x20;
+        }
+        push_base64(data, v, 5);
+    }
+}
+
+/// Writes the low `digits` base64 digits of `value`, least significant first.
+fn push_base64(data: &mut String, value: usize, digits: usize) {
+    for i in 0..digits {
+        data.push(BASE64_CHARS[value >> (6 * i) & 0x3F] as char);
+    }
+}
+
 fn decode_cell_color(value: usize) -> Option<CellColor> {
     Some(match value {
         0 => CellColor::Empty,
@@ -379,18 +754,12 @@ fn decode_cell_color(value: usize) -> Option<CellColor> {
     })
 }
 
-fn from_base64(c: char) -> Option<usize> {
-    Some(match c {
-        'A' ..= 'Z' => c as usize - 'A' as usize,
-        'a' ..= 'z' => c as usize - 'a' as usize + 26,
-        '0' ..= '9' => c as usize - '0' as usize + 52,
-        '+' => 62,
-        '/' => 63,
-        _ => return None
-    })
-}
 #[wasm_bindgen()]
 impl Page {
+    fn guideline_bit(&self, first: bool) -> bool {
+        self.guideline_switch != first
+    }
+
     fn fumen_number(&self) -> u32 {
         self.piece.map(|p| p.fumen_number()).unwrap_or(0) + 240 * 32 * (
             self.rise as u32 +
@@ -429,13 +798,7 @@ impl Page {
         if self.lock {
             let mut y = 0;
             for i in 0..23 {
-                let mut cleared = true;
-                for x in 0..10 {
-                    if field[i][x] == CellColor::Empty {
-                        cleared = false;
-                    }
-                }
-                if !cleared {
+                if field[i].contains(&CellColor::Empty) {
                     field[y] = field[i];
                     y += 1;
                 }
@@ -466,6 +829,7 @@ impl Page {
             rise: false,
             mirror: false,
             lock: self.lock,
+            guideline_switch: false,
             field,
             garbage_row: if self.rise {
                 [CellColor::Empty; 10]
@@ -555,68 +919,41 @@ impl Piece {
 
     fn fumen_pos(&self) -> u32 {
         // Convert true SRS piece centers to fumen's system
-        let x = match (self.kind, self.rotation) {
-            (PieceType::S, RotationState::East) => self.x + 1,
-            (PieceType::Z, RotationState::West) => self.x - 1,
-            (PieceType::O, RotationState::West) => self.x - 1,
-            (PieceType::O, RotationState::South) => self.x - 1,
-            (PieceType::I, RotationState::South) => self.x - 1,
-            _ => self.x
-        };
-        let y = match (self.kind, self.rotation) {
-            (PieceType::S, RotationState::North) => self.y + 1,
-            (PieceType::Z, RotationState::North) => self.y + 1,
-            (PieceType::O, RotationState::North) => self.y + 1,
-            (PieceType::O, RotationState::West) => self.y + 1,
-            (PieceType::I, RotationState::West) => self.y + 1,
-            _ => self.y
-        };
+        let (dx, dy) = coordinates::fumen_offset(self.kind, self.rotation);
+        let x = (self.x as i32 + dx) as u32;
+        let y = (self.y as i32 + dy) as u32;
 
         x + (22 - y) * 10
     }
+}
 
-    fn cells(&self) -> [(i32, i32); 4] {
-        let mut cells = match self.kind {
-            PieceType::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
-            PieceType::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
-            PieceType::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
-            PieceType::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
-            PieceType::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
-            PieceType::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
-            PieceType::Z => [(1, 0), (0, 0), (0, 1), (-1, 1)]
-        };
-
-        for (x, y) in &mut cells {
-            match self.rotation {
-                RotationState::North => {}
-                RotationState::East => {
-                    std::mem::swap(x, y);
-                    *y = -*y;
-                }
-                RotationState::South => {
-                    *x = -*x;
-                    *y = -*y;
-                }
-                RotationState::West => {
-                    std::mem::swap(x, y);
-                    *x = -*x;
-                }
-            }
-
-            *x += self.x as i32;
-            *y += self.y as i32;
-        }
+impl Piece {
+    /// The cells of the piece in the field, as `(x, y)` pairs.
+    pub fn cells(&self) -> [(i32, i32); 4] {
+        self.kind.offsets(self.rotation).map(|(x, y)| (x + self.x as i32, y + self.y as i32))
+    }
 
-        cells
+    /// Whether all of the piece's cells are in the 23 rows of the field.
+    pub(crate) fn in_field(&self) -> bool {
+        self.cells().iter().all(|&(x, y)| (0..10).contains(&x) && (0..23).contains(&y))
     }
 }
 
 impl Default for Fumen {
     fn default() -> Self {
-        Fumen {
-            pages: vec![],
-            guideline: true
-        }
+        Fumen::new()
+    }
+}
+// the source data is only a record of how the fumen was encoded, so it is not compared
+impl PartialEq for Fumen {
+    fn eq(&self, other: &Fumen) -> bool {
+        self.pages == other.pages
+    }
+}
+impl Eq for Fumen {}
+impl std::hash::Hash for Fumen {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.pages.hash(state);
     }
 }
 impl Default for Page {
@@ -628,35 +965,12 @@ impl Default for Page {
             rise: false,
             mirror: false,
             lock: true,
+            guideline_switch: false,
             comment: None
         }
     }
 }
 
-#[cfg(feature = "serde")]
-impl serde::Serialize for Fumen {
-    fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
-        ser.serialize_str(&self.encode())
-    }
-}
-
-#[cfg(feature = "serde")]
-impl<'de> serde::Deserialize<'de> for Fumen {
-    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
-        struct Visitor;
-        impl<'de> serde::de::Visitor<'de> for Visitor {
-            type Value = Fumen;
-            fn expecting(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
-                write!(fmt, "an encoded fumen string")
-            }
-            fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Fumen, E> {
-                Fumen::decode(s).map_err(E::custom)
-            }
-        }
-        de.deserialize_str(Visitor)
-    }
-}
-
 impl From<PieceType> for CellColor {
     fn from(v: PieceType) -> CellColor {
         match v {
@@ -717,7 +1031,7 @@ fn js_unescape(s: &str) -> String {
     }
 
     let mut iter = s.chars().peekable();
-    let mut result_utf16 = vec![];
+    let mut result_utf16 = Vec::with_capacity(s.len());
     while let Some(c) = iter.next() {
         match c {
             '%' => match iter.peek() {
@@ -746,6 +1060,7 @@ impl std::error::Error for DecodeFumenError {}
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
     use crate::*;
 
     #[test]
@@ -993,6 +1308,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn piece_outside_field() {
+        // an I piece below the field
+        assert_eq!(Fumen::decode("v115@vhAx3B"), Err(DecodeFumenError));
+        // an S piece sticking out of the left wall
+        assert_eq!(Fumen::decode("v115@vhAPyA"), Err(DecodeFumenError));
+    }
+
+    #[test]
+    fn split_unchanged_field() {
+        // an unchanged field written as two runs still starts an unchanged field sequence
+        let fumen = Fumen::decode("v115@vhBAgHAgH").unwrap();
+        assert_eq!(Fumen::decode("v115@3f3fBAgHAgH"), Ok(fumen));
+    }
+
     #[test]
     fn no_piece_lock() {
         unsafe {
@@ -1009,6 +1339,186 @@ mod tests {
             fumen.add_page();
             assert_eq!(fumen.encode(), "v115@bhJ8Je1KnvhA1qf");
             assert_eq!(Fumen::decode("v115@bhJ8Je1KnvhA1qf"), Ok(fumen));
-        } 
+        }
+    }
+
+    #[test]
+    fn lossless() {
+        for &data in &[
+            // guideline bit on the second page
+            "v115@vhBAgHAgH",
+            // separators, and position bits without a piece
+            "v115@vhBQ?gHAgH?",
+            // unchanged field spelled out instead of using vh, with a run longer than the fumen
+            "v115@3f3fCAgH",
+            // lowercase escapes in the comment
+            "v115@vhAAgWDAFuSCA",
+        ] {
+            let fumen = Fumen::decode_lossless(data).unwrap();
+            assert_eq!(fumen, Fumen::decode(data).unwrap());
+            assert_eq!(fumen.encode(), data);
+        }
+    }
+
+    #[test]
+    fn lossless_edit() {
+        let mut fumen = Fumen::decode_lossless("v115@vhBQgHAgH").unwrap();
+        fumen.pages[1].lock = false;
+        assert_eq!(fumen.encode(), "v115@vhBQgHAgl");
+        fumen.pages[1].field[0][0] = CellColor::Grey;
+        assert_eq!(fumen.encode(), "v115@vhAQgHbhA8SeAgl");
+    }
+
+    #[test]
+    fn guideline() {
+        let mut fumen = Fumen::decode("v115@vhCAAAAAAAgH").unwrap();
+        assert!(fumen.pages[0].guideline_switch);
+        assert!(!fumen.pages[1].guideline_switch);
+        assert!(fumen.pages[2].guideline_switch);
+        assert!(!fumen.guideline());
+        assert!(!fumen.guideline_at(1));
+        assert!(fumen.guideline_at(2));
+        assert_eq!(fumen.encode(), "v115@vhCAAAAAAAgH");
+
+        assert!(fumen.set_guideline(true));
+        assert!(fumen.guideline_at(1));
+        assert_eq!(fumen.encode(), "v115@vhCAgHAAAAgH");
+
+        // later pages can only switch to colour, so every combination of switches is kept
+        let mut fumen = Fumen::default();
+        assert!(!fumen.set_guideline(false));
+        assert!(fumen.guideline());
+        fumen.add_page();
+        fumen.add_page();
+        for &(first, second) in &[(false, false), (true, false), (true, true), (false, true)] {
+            fumen.pages[0].guideline_switch = first;
+            fumen.pages[1].guideline_switch = second;
+            assert_eq!(fumen.guideline_at(1), !first || second);
+            assert_eq!(Fumen::decode(&fumen.encode()).unwrap(), fumen);
+        }
+    }
+
+    #[test]
+    fn continuity() {
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).piece = Some(Piece {
+                kind: PieceType::T,
+                rotation: RotationState::North,
+                x: 2,
+                y: 0
+            });
+        }
+        fumen.add_page();
+        fumen.add_page();
+        assert_eq!(fumen.check_continuity(), Vec::<usize>::new());
+
+        // moving the piece on the first page leaves the second page stale
+        fumen.pages[0].piece.as_mut().unwrap().x = 5;
+        fumen.pages[2].field[3][0] = CellColor::Grey;
+        assert_eq!(fumen.check_continuity(), vec![1, 2]);
+    }
+
+    #[test]
+    fn normalize() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].comment = Some("Hello World!".to_owned());
+        fumen.pages[0].field[0][0] = CellColor::Grey;
+        fumen.add_page();
+        fumen.pages[1].field[1][0] = CellColor::Grey;
+        let original = fumen.clone();
+        fumen.normalize();
+        assert_eq!(fumen.pages[1].comment(), Some("Hello World!".to_owned()));
+        assert_eq!(fumen.pages[1].field, original.pages[1].field);
+        assert_eq!(fumen.check_continuity(), vec![1]);
+
+        let data = "v115@3f3fCAgH";
+        let mut fumen = Fumen::decode_lossless(data).unwrap();
+        fumen.normalize();
+        assert_eq!(fumen.encode(), data);
+    }
+
+    fn cell_color() -> impl Strategy<Value = CellColor> {
+        prop_oneof![3 => Just(CellColor::Empty), 1 => (1..9usize).prop_map(|v| {
+            decode_cell_color(v).unwrap()
+        })]
+    }
+
+    fn piece() -> impl Strategy<Value = Piece> {
+        let rotations = vec![
+            RotationState::South, RotationState::East, RotationState::North, RotationState::West
+        ];
+        (1..8i64, prop::sample::select(rotations), 0..10u32, 0..23u32).prop_filter_map(
+            "the piece must be inside the field",
+            |(kind, rotation, x, y)| {
+                let piece = Piece { kind: PieceType::from_i64(kind), rotation, x, y };
+                Some(piece).filter(Piece::in_field)
+            }
+        )
+    }
+
+    /// A page to go after the first page, and whether to give it the previous page's field.
+    fn page() -> impl Strategy<Value = (Page, bool)> {
+        let row = || prop::array::uniform10(cell_color());
+        (
+            prop::option::of(piece()),
+            any::<[bool; 4]>(),
+            prop::option::of(".{0,20}"),
+            prop::collection::vec(row(), 23),
+            row(),
+            any::<bool>()
+        ).prop_map(|(piece, [rise, mirror, lock, switch], comment, rows, garbage_row, same)| {
+            let mut page = Page {
+                piece, rise, mirror, lock, comment, garbage_row, ..Page::default()
+            };
+            page.guideline_switch = switch;
+            page.field.copy_from_slice(&rows);
+            (page, same)
+        })
+    }
+
+    fn fumen() -> impl Strategy<Value = Fumen> {
+        prop::collection::vec(page(), 0..10).prop_map(|pages| {
+            let mut fumen = Fumen::default();
+            for (mut page, same) in pages {
+                if let Some(prev) = fumen.pages.last().filter(|_| same) {
+                    let next = prev.next_page();
+                    page.field = next.field;
+                    page.garbage_row = next.garbage_row;
+                }
+                fumen.pages.push(page);
+            }
+            fumen
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(fumen in fumen()) {
+            let data = fumen.encode();
+            prop_assert_eq!(Fumen::decode(&data), Ok(fumen));
+            prop_assert_eq!(Fumen::decode_lossless(&data).unwrap().encode(), data);
+        }
+
+        #[test]
+        fn decode_any_string(data in "v115@[A-Za-z0-9+/?]{0,200}") {
+            if let Ok(fumen) = Fumen::decode_lossless(&data) {
+                prop_assert_eq!(fumen.encode(), data);
+            }
+        }
+
+        #[test]
+        fn decode_edited(
+            fumen in fumen(), index in any::<prop::sample::Index>(), c in any::<char>()
+        ) {
+            let mut data: Vec<char> = fumen.encode().chars().collect();
+            let index = index.index(data.len());
+            data[index] = c;
+            let data: String = data.into_iter().collect();
+            let _ = Fumen::decode(&data);
+            let _ = Fumen::pages_iter(&data).count();
+        }
     }
 }
\ No newline at end of file
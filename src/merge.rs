@@ -0,0 +1,356 @@
+use crate::diff::PageChange;
+use crate::{CellColor, Fumen, Page, Piece};
+
+/// The result of merging two fumens that were both edited from the same base.
+///
+/// Edits that only one side made, or that both sides made the same way, are merged
+/// automatically. Everything else is a [`Conflict`] that has to be resolved before the merged
+/// fumen is available.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Merge {
+    slots: Vec<Slot>,
+    conflicts: Vec<Conflict>
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Slot {
+    Pages(Vec<Page>),
+    /// a page merged from both sides, which the cell conflicts of `base_page` apply to
+    Merged { base_page: usize, page: Box<Page> },
+    /// a page conflict, indexing into `conflicts`
+    Conflict(usize)
+}
+
+/// An edit that was made differently by both sides.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Conflict {
+    /// The index of the base page the conflict is on. For conflicting inserted pages, this is
+    /// the index of the base page they are inserted before.
+    pub base_page: usize,
+    pub kind: ConflictKind,
+    pub resolution: Option<Resolution>
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ConflictKind {
+    /// Both sides inserted different pages at the same place, or one side changed pages the
+    /// other side removed.
+    Pages { base: Vec<Page>, ours: Vec<Page>, theirs: Vec<Page> },
+    /// y-up
+    Cell { x: usize, y: usize, base: CellColor, ours: CellColor, theirs: CellColor },
+    GarbageCell { x: usize, base: CellColor, ours: CellColor, theirs: CellColor },
+    Piece { base: Option<Piece>, ours: Option<Piece>, theirs: Option<Piece> },
+    Guideline { base: bool, ours: bool, theirs: bool },
+    Comment { base: Option<String>, ours: Option<String>, theirs: Option<String> }
+}
+
+/// Which version of a conflicting edit to keep.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Resolution {
+    Base,
+    Ours,
+    Theirs
+}
+
+impl Fumen {
+    /// Merges the edits `ours` and `theirs` made to `base`.
+    ///
+    /// Pages are matched up with [`Fumen::diff`]. Pages that both sides kept are merged cell by
+    /// cell, along with their piece, flags and comment. Pages inserted by only one side are
+    /// kept, and pages removed by one side are removed unless the other side changed them.
+    pub fn merge(base: &Fumen, ours: &Fumen, theirs: &Fumen) -> Merge {
+        let ours_side = Side::new(base, ours);
+        let theirs_side = Side::new(base, theirs);
+        let mut merge = Merge { slots: vec![], conflicts: vec![] };
+
+        for i in 0..=base.pages.len() {
+            let ours_inserted = &ours_side.inserted[i];
+            let theirs_inserted = &theirs_side.inserted[i];
+            if theirs_inserted.is_empty() || ours_inserted == theirs_inserted {
+                merge.slots.push(Slot::Pages(ours_inserted.clone()));
+            } else if ours_inserted.is_empty() {
+                merge.slots.push(Slot::Pages(theirs_inserted.clone()));
+            } else {
+                merge.page_conflict(i, vec![], ours_inserted.clone(), theirs_inserted.clone());
+            }
+
+            let base_page = match base.pages.get(i) {
+                Some(page) => page,
+                None => break
+            };
+            match (ours_side.kept[i].as_ref(), theirs_side.kept[i].as_ref()) {
+                (Some(o), Some(t)) => {
+                    let page = merge.merge_page(i, base_page, o, t);
+                    merge.slots.push(Slot::Merged { base_page: i, page: Box::new(page) });
+                }
+                (None, None) => {}
+                (None, Some(kept)) | (Some(kept), None) if kept == base_page => {}
+                (o, t) => merge.page_conflict(
+                    i,
+                    vec![base_page.clone()],
+                    o.cloned().into_iter().collect(),
+                    t.cloned().into_iter().collect()
+                )
+            }
+        }
+        merge
+    }
+}
+
+/// One side of a merge, in terms of the base pages.
+struct Side {
+    /// the side's version of each base page, or `None` if it was removed
+    kept: Vec<Option<Page>>,
+    /// the pages inserted before each base page, and at the end
+    inserted: Vec<Vec<Page>>
+}
+
+impl Side {
+    fn new(base: &Fumen, side: &Fumen) -> Side {
+        let mut kept = vec![None; base.pages.len()];
+        let mut inserted = vec![vec![]; base.pages.len() + 1];
+        let mut gap = 0;
+        for change in base.diff(side).pages {
+            match change {
+                PageChange::Unchanged { old, new } | PageChange::Changed { old, new, .. } => {
+                    kept[old] = Some(side.pages[new].clone());
+                    gap = old + 1;
+                }
+                PageChange::Removed { old } => gap = old + 1,
+                PageChange::Inserted { new } => inserted[gap].push(side.pages[new].clone())
+            }
+        }
+        Side { kept, inserted }
+    }
+}
+
+/// Merges a single value, returning `None` if both sides changed it differently.
+fn merge3<T: PartialEq + Clone>(base: &T, ours: &T, theirs: &T) -> Option<T> {
+    if ours == base {
+        Some(theirs.clone())
+    } else if theirs == base || ours == theirs {
+        Some(ours.clone())
+    } else {
+        None
+    }
+}
+
+impl Merge {
+    fn page_conflict(
+        &mut self, base_page: usize, base: Vec<Page>, ours: Vec<Page>, theirs: Vec<Page>
+    ) {
+        self.slots.push(Slot::Conflict(self.conflicts.len()));
+        self.conflicts.push(Conflict {
+            base_page,
+            kind: ConflictKind::Pages { base, ours, theirs },
+            resolution: None
+        });
+    }
+
+    fn conflict(&mut self, base_page: usize, kind: ConflictKind) {
+        self.conflicts.push(Conflict { base_page, kind, resolution: None });
+    }
+
+    /// Merges the two sides' versions of a page, keeping the base version of anything that
+    /// conflicts.
+    fn merge_page(&mut self, index: usize, base: &Page, ours: &Page, theirs: &Page) -> Page {
+        let mut page = base.clone();
+        for y in 0..23 {
+            for x in 0..10 {
+                let (b, o, t) = (base.field[y][x], ours.field[y][x], theirs.field[y][x]);
+                match merge3(&b, &o, &t) {
+                    Some(cell) => page.field[y][x] = cell,
+                    None => self.conflict(index, ConflictKind::Cell {
+                        x, y, base: b, ours: o, theirs: t
+                    })
+                }
+            }
+        }
+        for x in 0..10 {
+            let (b, o, t) = (base.garbage_row[x], ours.garbage_row[x], theirs.garbage_row[x]);
+            match merge3(&b, &o, &t) {
+                Some(cell) => page.garbage_row[x] = cell,
+                None => self.conflict(index, ConflictKind::GarbageCell {
+                    x, base: b, ours: o, theirs: t
+                })
+            }
+        }
+        match merge3(&base.piece, &ours.piece, &theirs.piece) {
+            Some(piece) => page.piece = piece,
+            None => self.conflict(index, ConflictKind::Piece {
+                base: base.piece, ours: ours.piece, theirs: theirs.piece
+            })
+        }
+        match merge3(&base.guideline_switch, &ours.guideline_switch, &theirs.guideline_switch) {
+            Some(switch) => page.guideline_switch = switch,
+            None => self.conflict(index, ConflictKind::Guideline {
+                base: base.guideline_switch,
+                ours: ours.guideline_switch,
+                theirs: theirs.guideline_switch
+            })
+        }
+        match merge3(&base.comment, &ours.comment, &theirs.comment) {
+            Some(comment) => page.comment = comment,
+            None => self.conflict(index, ConflictKind::Comment {
+                base: base.comment.clone(),
+                ours: ours.comment.clone(),
+                theirs: theirs.comment.clone()
+            })
+        }
+        // flags only have two values, so they can't conflict
+        page.rise = merge3(&base.rise, &ours.rise, &theirs.rise).unwrap();
+        page.mirror = merge3(&base.mirror, &ours.mirror, &theirs.mirror).unwrap();
+        page.lock = merge3(&base.lock, &ours.lock, &theirs.lock).unwrap();
+        page
+    }
+
+    pub fn conflicts(&self) -> &[Conflict] {
+        &self.conflicts
+    }
+
+    /// Chooses which version of the `conflict`th conflict to keep.
+    pub fn resolve(&mut self, conflict: usize, resolution: Resolution) {
+        self.conflicts[conflict].resolution = Some(resolution);
+    }
+
+    /// Resolves every conflict that has not been resolved yet the same way.
+    pub fn resolve_remaining(&mut self, resolution: Resolution) {
+        for conflict in &mut self.conflicts {
+            conflict.resolution.get_or_insert(resolution);
+        }
+    }
+
+    /// The merged fumen, if every conflict has been resolved.
+    pub fn fumen(&self) -> Result<Fumen, MergeConflictError> {
+        let unresolved = self.conflicts.iter().filter(|c| c.resolution.is_none()).count();
+        if unresolved > 0 {
+            return Err(MergeConflictError { unresolved });
+        }
+
+        let mut fumen = Fumen::new();
+        for slot in &self.slots {
+            match slot {
+                Slot::Pages(pages) => fumen.pages.extend(pages.iter().cloned()),
+                &Slot::Conflict(i) => match self.conflicts[i].kind {
+                    ConflictKind::Pages { ref base, ref ours, ref theirs } => {
+                        fumen.pages.extend(match self.conflicts[i].resolution {
+                            Some(Resolution::Base) => base,
+                            Some(Resolution::Ours) => ours,
+                            _ => theirs
+                        }.iter().cloned())
+                    }
+                    _ => unreachable!()
+                },
+                &Slot::Merged { base_page, ref page } => {
+                    let mut page = Page::clone(page);
+                    for conflict in &self.conflicts {
+                        if conflict.base_page == base_page {
+                            conflict.apply(&mut page);
+                        }
+                    }
+                    fumen.pages.push(page);
+                }
+            }
+        }
+        Ok(fumen)
+    }
+}
+
+impl Conflict {
+    fn apply(&self, page: &mut Page) {
+        fn pick<T: Clone>(resolution: Option<Resolution>, base: &T, ours: &T, theirs: &T) -> T {
+            match resolution {
+                Some(Resolution::Base) => base,
+                Some(Resolution::Ours) => ours,
+                _ => theirs
+            }.clone()
+        }
+        let r = self.resolution;
+        match self.kind {
+            ConflictKind::Pages { .. } => {}
+            ConflictKind::Cell { x, y, base, ours, theirs } =>
+                page.field[y][x] = pick(r, &base, &ours, &theirs),
+            ConflictKind::GarbageCell { x, base, ours, theirs } =>
+                page.garbage_row[x] = pick(r, &base, &ours, &theirs),
+            ConflictKind::Piece { base, ours, theirs } =>
+                page.piece = pick(r, &base, &ours, &theirs),
+            ConflictKind::Guideline { base, ours, theirs } =>
+                page.guideline_switch = pick(r, &base, &ours, &theirs),
+            ConflictKind::Comment { ref base, ref ours, ref theirs } =>
+                page.comment = pick(r, base, ours, theirs)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct MergeConflictError {
+    pub unresolved: usize
+}
+
+impl std::fmt::Display for MergeConflictError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "the merge has {} unresolved conflicts", self.unresolved)
+    }
+}
+
+impl std::error::Error for MergeConflictError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::merge::*;
+
+    fn base() -> Fumen {
+        let mut fumen = Fumen::default();
+        for i in 0..3 {
+            fumen.add_page();
+            fumen.pages[i].comment = Some(i.to_string());
+        }
+        fumen
+    }
+
+    #[test]
+    fn clean_merge() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.pages[0].field[0][0] = CellColor::Grey;
+        ours.pages.push(Page::default());
+        let mut theirs = base.clone();
+        theirs.pages[0].field[0][9] = CellColor::I;
+        theirs.pages.remove(1);
+
+        let merge = Fumen::merge(&base, &ours, &theirs);
+        assert_eq!(merge.conflicts(), &[]);
+        let merged = merge.fumen().unwrap();
+        assert_eq!(merged.pages.len(), 3);
+        assert_eq!(merged.pages[0].field[0][0], CellColor::Grey);
+        assert_eq!(merged.pages[0].field[0][9], CellColor::I);
+        assert_eq!(merged.pages[1].comment(), Some("2".to_owned()));
+        assert_eq!(merged.pages[2], Page::default());
+    }
+
+    #[test]
+    fn conflicts() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.pages[1].field[0][0] = CellColor::Grey;
+        ours.pages[2].comment = Some("ours".to_owned());
+        let mut theirs = base.clone();
+        theirs.pages[1].field[0][0] = CellColor::T;
+        theirs.pages.remove(2);
+
+        let mut merge = Fumen::merge(&base, &ours, &theirs);
+        assert_eq!(merge.conflicts().len(), 2);
+        assert_eq!(merge.conflicts()[0].kind, ConflictKind::Cell {
+            x: 0, y: 0, base: CellColor::Empty, ours: CellColor::Grey, theirs: CellColor::T
+        });
+        assert!(matches!(merge.conflicts()[1].kind, ConflictKind::Pages { .. }));
+        assert_eq!(merge.fumen(), Err(MergeConflictError { unresolved: 2 }));
+
+        merge.resolve(0, Resolution::Theirs);
+        merge.resolve_remaining(Resolution::Ours);
+        let merged = merge.fumen().unwrap();
+        assert_eq!(merged.pages[1].field[0][0], CellColor::T);
+        assert_eq!(merged.pages[2].comment(), Some("ours".to_owned()));
+    }
+}
@@ -0,0 +1,160 @@
+use wasm_bindgen::prelude::*;
+
+use crate::quiz::Quiz;
+use crate::{fumen_field_delta, Fumen, Page};
+
+#[wasm_bindgen]
+impl Fumen {
+    /// Encode as the shortest fumen data string that shows the same pages.
+    ///
+    /// Two pages show the same thing if they have the same field, garbage row, piece, colour,
+    /// and comment as the fumen editor displays it. The lock, rise and mirror flags of each page
+    /// only decide how the next page is derived, so they are chosen to keep the next page's field
+    /// unchanged where possible, and comments that repeat the previous page's comment are
+    /// dropped. Quiz comments are kept, since the editor advances an inherited quiz through the
+    /// pieces used but shows a restated one as written. The result is checked by decoding it, and
+    /// falls back to `encode` if it does not show the same pages or is not shorter.
+    #[wasm_bindgen(js_name = "encodeOptimized")]
+    pub fn encode_optimized(&self) -> String {
+        let plain = self.encode();
+
+        let mut fumen = self.clone();
+        let mut comment = None;
+        for i in 0..fumen.pages.len() {
+            if let Some(next) = self.pages.get(i + 1) {
+                let page = &mut fumen.pages[i];
+                let mut best = (page.lock, page.rise, page.mirror);
+                let mut best_cost = follow_on_cost(page, next);
+                for &lock in &[true, false] {
+                    for &rise in &[false, true] {
+                        for &mirror in &[false, true] {
+                            page.lock = lock;
+                            page.rise = rise;
+                            page.mirror = mirror;
+                            let cost = follow_on_cost(page, next);
+                            if cost < best_cost {
+                                best = (lock, rise, mirror);
+                                best_cost = cost;
+                            }
+                        }
+                    }
+                }
+                page.lock = best.0;
+                page.rise = best.1;
+                page.mirror = best.2;
+            }
+
+            let page = &mut fumen.pages[i];
+            let quiz = page.comment.as_deref().and_then(Quiz::parse).is_some();
+            if page.comment.is_some() && page.comment == comment && !quiz {
+                page.comment = None;
+            } else if page.comment.is_some() {
+                comment = page.comment.clone();
+            }
+        }
+
+        let optimized = fumen.encode();
+        if optimized.len() < plain.len() && shows_same_pages(self, &optimized) {
+            optimized
+        } else {
+            plain
+        }
+    }
+}
+
+/// The number of characters needed to encode `next`'s field after `page`.
+///
+/// An unchanged field is counted as free, since it is usually part of a run of unchanged fields
+/// that share a single `vh` marker.
+fn follow_on_cost(page: &Page, next: &Page) -> usize {
+    let deltas = fumen_field_delta(page.next_page().fumen_field(), next.fumen_field());
+    if deltas == [[8; 10]; 24] {
+        return 0;
+    }
+    let mut runs = 1;
+    let mut prev = deltas[0][0];
+    for &delta in deltas.iter().flatten() {
+        if delta != prev {
+            runs += 1;
+            prev = delta;
+        }
+    }
+    runs * 2
+}
+
+fn shows_same_pages(fumen: &Fumen, data: &str) -> bool {
+    let mut actual = match Fumen::decode(data) {
+        Ok(fumen) => fumen,
+        Err(_) => return false
+    };
+    // inherited quiz comments are shown differently, so they must be written on the same pages
+    let quizzes = |fumen: &Fumen| -> Vec<_> {
+        fumen.pages.iter().map(|page| page.comment.as_deref().and_then(Quiz::parse)).collect()
+    };
+    if quizzes(fumen) != quizzes(&actual) {
+        return false;
+    }
+    let mut expected = fumen.clone();
+    expected.normalize();
+    actual.normalize();
+    expected.pages.len() == actual.pages.len() &&
+        expected.pages.iter().zip(&actual.pages).enumerate().all(|(i, (e, a))| {
+            e.field == a.field && e.garbage_row == a.garbage_row &&
+                e.piece == a.piece && e.comment == a.comment &&
+                expected.guideline_at(i) == actual.guideline_at(i)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::quiz::*;
+
+    #[test]
+    fn drops_repeated_comments() {
+        let mut fumen = Fumen::default();
+        for _ in 0..3 {
+            fumen.add_page();
+        }
+        for page in fumen.get_pages_mut() {
+            page.set_comment_rs(Some("Hello World!".to_owned()));
+        }
+        assert_eq!(fumen.encode_optimized(), "v115@vhCAgWQAIoMDEvoo2AXXaDEkoA6AAAAAAA");
+        assert!(fumen.encode_optimized().len() < fumen.encode().len());
+    }
+
+    #[test]
+    fn keeps_repeated_quiz_comments() {
+        let mut fumen = Fumen::default();
+        for _ in 0..3 {
+            let page = fumen.add_page();
+            unsafe {
+                (*page).set_comment_rs(Some("#Q=[](T)SZOJ".to_owned()));
+            }
+        }
+        let quizzes = |fumen: &Fumen| -> Vec<_> {
+            fumen.get_pages().iter()
+                .map(|page| page.comment().and_then(|comment| Quiz::parse(&comment)))
+                .collect()
+        };
+        let decoded = Fumen::decode(&fumen.encode_optimized()).unwrap();
+        assert_eq!(quizzes(&decoded), quizzes(&fumen));
+    }
+
+    #[test]
+    fn unlocks_to_keep_field() {
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).field[0] = [CellColor::Grey; 10];
+        }
+        let mut next = fumen.get_pages()[0].clone();
+        next.lock = true;
+        fumen.get_pages_mut().push(next);
+        let optimized = fumen.encode_optimized();
+        assert!(optimized.len() < fumen.encode().len());
+        let decoded = Fumen::decode(&optimized).unwrap();
+        assert!(!decoded.get_pages()[0].lock);
+        assert_eq!(decoded.get_pages()[1].get_field(), fumen.get_pages()[1].get_field());
+    }
+}
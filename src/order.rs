@@ -0,0 +1,210 @@
+//! Finding the orders a finished shape can be built in.
+
+use std::collections::HashSet;
+
+use crate::coordinates;
+use crate::infer::push_placements;
+use crate::srs::{self, KickTable};
+use crate::{CellColor, Fumen, Page, Piece};
+
+/// Finds every order `pieces` can be placed in on `field`, one at a time.
+///
+/// Each piece has to be reachable from its spawn position using SRS with the given kicks, and
+/// rest on the field or the pieces placed before it. Lines that fill up are cleared as soon as
+/// they do, so the pieces are given where they are in the finished shape, before any lines are
+/// cleared. Returns no orders if the pieces overlap each other or the field.
+///
+/// Each order is a fumen with a page for each piece, showing the field it is placed on, and a
+/// last page showing the result.
+pub fn placement_orders(
+    field: &[[CellColor; 10]; 23], pieces: &[Piece], kicks: KickTable
+) -> Vec<Fumen> {
+    let start = Page { field: *field, ..Page::default() };
+    orders(&start, pieces, kicks)
+}
+
+impl Page {
+    /// Finds every order the coloured cells of the field can be built in, with pieces of their
+    /// colour, starting from the grey cells.
+    ///
+    /// The coloured cells are split into pieces by [`Page::find_pieces`], and returns no orders if
+    /// they don't split into pieces. The first page of each order keeps this page's comment. See
+    /// [`placement_orders`].
+    pub fn placement_orders(&self, kicks: KickTable) -> Vec<Fumen> {
+        let pieces = match self.find_pieces() {
+            Some(pieces) => pieces,
+            None => return vec![]
+        };
+        let mut start = Page { piece: None, ..self.clone() };
+        for cell in start.field.iter_mut().flatten() {
+            if *cell != CellColor::Grey {
+                *cell = CellColor::Empty;
+            }
+        }
+        orders(&start, &pieces, kicks)
+    }
+}
+
+fn orders(start: &Page, pieces: &[Piece], kicks: KickTable) -> Vec<Fumen> {
+    let mut field = start.field;
+    for piece in pieces {
+        if !piece.in_field() {
+            return vec![];
+        }
+        for &(x, y) in &piece.cells() {
+            let cell = &mut field[y as usize][x as usize];
+            if *cell != CellColor::Empty {
+                return vec![];
+            }
+            *cell = piece.kind.into();
+        }
+    }
+
+    let mut search = Search { kicks, dead: HashSet::new(), order: vec![], orders: vec![] };
+    search.search(&start.field, pieces, 0);
+    search.orders.into_iter().map(|order| {
+        let pieces: Vec<_> = order.iter().map(|&i| pieces[i]).collect();
+        let mut pages = vec![];
+        push_placements(&mut pages, start.clone(), &pieces);
+        let last = pages.last().map_or_else(|| start.clone(), Page::next_page);
+        pages.push(last);
+        Fumen { pages, source: None }
+    }).collect()
+}
+
+struct Search {
+    kicks: KickTable,
+    /// Sets of placed pieces that can't be finished from.
+    dead: HashSet<u64>,
+    order: Vec<usize>,
+    orders: Vec<Vec<usize>>
+}
+
+impl Search {
+    /// Finds the orders the rest of the pieces can be placed in, with `pieces` moved to where
+    /// they are in `field`. Returns whether any were found.
+    ///
+    /// Which lines have been cleared only depends on which pieces have been placed, so sets of
+    /// placed pieces that can't be finished from are remembered.
+    fn search(&mut self, field: &[[CellColor; 10]; 23], pieces: &[Piece], placed: u64) -> bool {
+        if self.order.len() == pieces.len() {
+            self.orders.push(self.order.clone());
+            return true;
+        }
+        if self.dead.contains(&placed) {
+            return false;
+        }
+
+        let mut found = false;
+        for (i, &piece) in pieces.iter().enumerate() {
+            if placed & 1 << i != 0 || !can_place(piece, field, self.kicks) {
+                continue;
+            }
+
+            let mut next_field = *field;
+            for &(x, y) in &piece.cells() {
+                next_field[y as usize][x as usize] = piece.kind.into();
+            }
+            let mut cleared = vec![];
+            let mut y = 0;
+            for row in 0..23 {
+                if next_field[row].contains(&CellColor::Empty) {
+                    next_field[y] = next_field[row];
+                    y += 1;
+                } else {
+                    cleared.push(row as u32);
+                }
+            }
+            for row in &mut next_field[y..] {
+                *row = [CellColor::Empty; 10];
+            }
+            // pieces still to be placed never cover a cleared line, since it was full without them
+            let next_pieces: Vec<_> = pieces.iter().map(|&piece| {
+                let below = cleared.iter().filter(|&&row| row < piece.y).count() as u32;
+                Piece { y: piece.y - below, ..piece }
+            }).collect();
+
+            self.order.push(i);
+            found |= self.search(&next_field, &next_pieces, placed | 1 << i);
+            self.order.pop();
+        }
+        if !found {
+            self.dead.insert(placed);
+        }
+        found
+    }
+}
+
+/// Whether `piece` can be moved into place from the spawn position and locked there.
+fn can_place(piece: Piece, field: &[[CellColor; 10]; 23], kicks: KickTable) -> bool {
+    if !piece.on_ground(field) {
+        return false;
+    }
+    let spawn = coordinates::spawn(piece.kind);
+    // pieces with the same cells in another rotation are the same placement
+    let mut cells = piece.cells();
+    cells.sort_unstable();
+    srs::reachable(spawn, field, kicks).iter().any(|other| {
+        let mut other_cells = other.cells();
+        other_cells.sort_unstable();
+        other_cells == cells
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::order::*;
+
+    #[test]
+    fn gravity() {
+        let field = [[CellColor::Empty; 10]; 23];
+        let o = Piece { kind: PieceType::O, rotation: RotationState::North, x: 0, y: 0 };
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 1, y: 2 };
+        let orders = placement_orders(&field, &[t, o], KickTable::Srs);
+        assert_eq!(orders.len(), 1);
+        let pages = orders[0].get_pages();
+        assert_eq!(pages.len(), 3);
+        assert_eq!(pages[0].piece, Some(o));
+        assert_eq!(pages[1].piece, Some(t));
+        assert!(orders[0].check_continuity().is_empty());
+
+        assert!(placement_orders(&field, &[o, o], KickTable::Srs).is_empty());
+    }
+
+    #[test]
+    fn line_clears() {
+        let mut page = Page::default();
+        page.field[0] = [CellColor::Grey; 10];
+        page.field[0][..4].copy_from_slice(&[CellColor::I; 4]);
+        page.field[1][4..6].copy_from_slice(&[CellColor::O; 2]);
+        page.field[2][4..6].copy_from_slice(&[CellColor::O; 2]);
+        page.comment = Some("build".to_owned());
+        let orders = page.placement_orders(KickTable::Srs);
+        assert_eq!(orders.len(), 2);
+        for fumen in &orders {
+            assert!(fumen.check_continuity().is_empty());
+            assert_eq!(fumen.get_pages()[0].comment(), Some("build".to_owned()));
+            let last = fumen.get_pages()[2].get_field();
+            assert_eq!(last[0][4..6], [CellColor::O; 2]);
+            assert_eq!(last[1][4..6], [CellColor::O; 2]);
+        }
+        // the O placed after the line is cleared is moved down
+        let o = |y| Some(Piece { kind: PieceType::O, rotation: RotationState::North, x: 4, y });
+        assert_eq!(orders[0].get_pages()[1].piece, o(0));
+        assert_eq!(orders[1].get_pages()[0].piece, o(1));
+    }
+
+    #[test]
+    fn reachability() {
+        // an I piece under a roof can't be reached
+        let mut field = [[CellColor::Empty; 10]; 23];
+        field[0][4..].copy_from_slice(&[CellColor::Grey; 6]);
+        field[1] = [CellColor::Grey; 10];
+        let i = Piece { kind: PieceType::I, rotation: RotationState::North, x: 1, y: 0 };
+        assert!(placement_orders(&field, &[i], KickTable::Srs).is_empty());
+        // but it can be kicked in from a vertical I in a hole in the roof
+        field[1][0] = CellColor::Empty;
+        assert_eq!(placement_orders(&field, &[i], KickTable::Srs).len(), 1);
+    }
+}
@@ -0,0 +1,105 @@
+//! The quiz comments fumen uses to give the pieces available on a page.
+//!
+//! A quiz comment is written `#Q=[H](C)NEXT`, where `H` is the held piece, `C` the current piece
+//! and `NEXT` the rest of the queue, each written as piece letters. The hold and current piece
+//! may be empty.
+
+use crate::PieceType;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Quiz {
+    pub hold: Option<PieceType>,
+    pub current: Option<PieceType>,
+    pub next: Vec<PieceType>
+}
+
+impl Quiz {
+    /// Parses a quiz comment, returning `None` if the comment is not a quiz.
+    ///
+    /// Anything after a `;` following the queue is ignored.
+    pub fn parse(comment: &str) -> Option<Quiz> {
+        let rest = comment.strip_prefix("#Q=[")?;
+        let (hold, rest) = split_piece(rest, ']')?;
+        let rest = rest.strip_prefix('(')?;
+        let (current, rest) = split_piece(rest, ')')?;
+        let queue = rest.split(';').next().unwrap_or("");
+        let next = queue.chars().map(PieceType::from_char).collect::<Option<_>>()?;
+        Some(Quiz { hold, current, next })
+    }
+
+    /// The quiz starting a game with an empty hold, taking the current piece and `previews` next
+    /// pieces from `queue`, such as one of the [randomizers](crate::randomizer).
+    ///
+    /// The queue is shorter if `queue` runs out.
+    pub fn from_queue(queue: impl IntoIterator<Item = PieceType>, previews: usize) -> Quiz {
+        let mut queue = queue.into_iter();
+        let current = queue.next();
+        Quiz { hold: None, current, next: queue.take(previews).collect() }
+    }
+}
+
+fn split_piece(s: &str, end: char) -> Option<(Option<PieceType>, &str)> {
+    let close = s.find(end)?;
+    let piece = match &s[..close] {
+        "" => None,
+        letter if letter.chars().count() == 1 => {
+            Some(PieceType::from_char(letter.chars().next()?)?)
+        }
+        _ => return None
+    };
+    Some((piece, &s[close + 1..]))
+}
+
+impl std::fmt::Display for Quiz {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "#Q=[")?;
+        if let Some(hold) = self.hold {
+            write!(fmt, "{}", hold.to_char())?;
+        }
+        write!(fmt, "](")?;
+        if let Some(current) = self.current {
+            write!(fmt, "{}", current.to_char())?;
+        }
+        write!(fmt, ")")?;
+        for piece in &self.next {
+            write!(fmt, "{}", piece.to_char())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::quiz::*;
+
+    #[test]
+    fn round_trip() {
+        let quiz = Quiz::parse("#Q=[](T)SZOJ").unwrap();
+        assert_eq!(quiz, Quiz {
+            hold: None,
+            current: Some(PieceType::T),
+            next: vec![PieceType::S, PieceType::Z, PieceType::O, PieceType::J]
+        });
+        assert_eq!(quiz.to_string(), "#Q=[](T)SZOJ");
+        let quiz = Quiz::parse("#Q=[I](L);hello").unwrap();
+        assert_eq!(quiz.hold, Some(PieceType::I));
+        assert_eq!(quiz.next, vec![]);
+        assert_eq!(Quiz::parse("#Q=[X](T)"), None);
+        assert_eq!(Quiz::parse("hello"), None);
+    }
+
+    #[test]
+    fn from_queue() {
+        use PieceType::*;
+        let quiz = Quiz::from_queue(vec![T, I, O, S], 2);
+        assert_eq!(quiz.to_string(), "#Q=[](T)IO");
+        assert_eq!(Quiz::from_queue(vec![], 5), Quiz::default());
+
+        let mut bag = randomizer::TetrioBag::new(1);
+        let quiz = Quiz::from_queue(&mut bag, 5);
+        let mut queue: Vec<_> = quiz.current.into_iter().chain(quiz.next).collect();
+        queue.push(bag.next().unwrap());
+        assert_eq!(queue, randomizer::TetrioBag::new(1).take(7).collect::<Vec<_>>());
+    }
+}
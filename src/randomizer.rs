@@ -0,0 +1,202 @@
+//! The randomizers games use to pick pieces, as endless iterators over the pieces they give.
+//!
+//! Each randomizer is seeded the way its game is, so the same seed gives the same queue as the
+//! game did.
+
+use crate::rng::{Alea, ParkMiller, Tgm};
+use crate::PieceType;
+
+/// TETR.IO's 7-bag randomizer, which shuffles each bag with [a Park-Miller generator].
+///
+/// [a Park-Miller generator]: https://en.wikipedia.org/wiki/Lehmer_random_number_generator
+pub struct TetrioBag {
+    rng: ParkMiller,
+    /// The rest of the current bag, in reverse order.
+    bag: Vec<PieceType>
+}
+
+impl TetrioBag {
+    /// The randomizer for a game with the `seed` in its options.
+    pub fn new(seed: u32) -> TetrioBag {
+        TetrioBag { rng: ParkMiller::new(seed as f64), bag: vec![] }
+    }
+}
+
+impl Iterator for TetrioBag {
+    type Item = PieceType;
+
+    fn next(&mut self) -> Option<PieceType> {
+        if self.bag.is_empty() {
+            use PieceType::*;
+            let mut bag = vec![Z, L, O, S, I, J, T];
+            for i in (1..bag.len()).rev() {
+                let j = (self.rng.next_float() * (i + 1) as f64) as usize;
+                bag.swap(i, j);
+            }
+            bag.reverse();
+            self.bag = bag;
+        }
+        self.bag.pop()
+    }
+}
+
+/// Jstris' 7-bag randomizer, which picks each piece at random from what is left of the bag.
+pub struct JstrisBag {
+    rng: Alea,
+    bag: Vec<PieceType>
+}
+
+impl JstrisBag {
+    /// The randomizer for a game with the `seed` in its replay.
+    pub fn new(seed: &str) -> JstrisBag {
+        JstrisBag { rng: Alea::new(seed), bag: vec![] }
+    }
+}
+
+impl Iterator for JstrisBag {
+    type Item = PieceType;
+
+    fn next(&mut self) -> Option<PieceType> {
+        if self.bag.is_empty() {
+            use PieceType::*;
+            self.bag = vec![I, O, T, L, J, S, Z];
+        }
+        let index = (self.rng.next() * self.bag.len() as f64) as usize;
+        Some(self.bag.remove(index))
+    }
+}
+
+/// Picks every piece at random from all seven, as the earliest games did, using the same
+/// generator as [`TetrioBag`].
+pub struct Memoryless {
+    rng: ParkMiller
+}
+
+impl Memoryless {
+    /// The randomizer seeded with `seed`.
+    pub fn new(seed: u32) -> Memoryless {
+        Memoryless { rng: ParkMiller::new(seed as f64) }
+    }
+}
+
+impl Iterator for Memoryless {
+    type Item = PieceType;
+
+    fn next(&mut self) -> Option<PieceType> {
+        use PieceType::*;
+        Some([I, O, T, L, J, S, Z][self.rng.next_below(7)])
+    }
+}
+
+/// The history randomizer of the Tetris: The Grand Master games.
+///
+/// It remembers the last four pieces, and picks again up to a number of times while the piece
+/// picked is one of them. The first piece is never an S, Z or O.
+pub struct History {
+    rng: Tgm,
+    /// The last four pieces, most recent first.
+    history: [PieceType; 4],
+    rolls: usize,
+    first: bool
+}
+
+impl History {
+    /// The randomizer of Tetris: The Grand Master, which picks up to 4 times and starts with a
+    /// history of four Z pieces.
+    pub fn tgm(seed: u32) -> History {
+        use PieceType::*;
+        History { rng: Tgm::new(seed), history: [Z, Z, Z, Z], rolls: 4, first: true }
+    }
+
+    /// The randomizer of Tetris: The Absolute The Grand Master 2, which picks up to 6 times and
+    /// starts with a history of Z, S, S and Z.
+    pub fn tgm2(seed: u32) -> History {
+        use PieceType::*;
+        History { rng: Tgm::new(seed), history: [Z, S, S, Z], rolls: 6, first: true }
+    }
+
+    fn roll(&mut self) -> PieceType {
+        use PieceType::*;
+        [I, Z, S, J, L, O, T][self.rng.next() as usize % 7]
+    }
+}
+
+impl Iterator for History {
+    type Item = PieceType;
+
+    fn next(&mut self) -> Option<PieceType> {
+        let mut piece = self.roll();
+        if self.first {
+            self.first = false;
+            while let PieceType::S | PieceType::Z | PieceType::O = piece {
+                piece = self.roll();
+            }
+        } else {
+            for _ in 1..self.rolls {
+                if !self.history.contains(&piece) {
+                    break;
+                }
+                piece = self.roll();
+            }
+        }
+        self.history.rotate_right(1);
+        self.history[0] = piece;
+        Some(piece)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::randomizer::*;
+
+    fn assert_bags(pieces: &[PieceType]) {
+        for bag in pieces.chunks(7) {
+            let mut bag = bag.to_vec();
+            bag.sort_by_key(|&p| p as u8);
+            bag.dedup();
+            assert_eq!(bag.len(), 7);
+        }
+    }
+
+    #[test]
+    fn tetrio_bag() {
+        let pieces: Vec<_> = TetrioBag::new(123456).take(14).collect();
+        assert_bags(&pieces);
+        assert_eq!(pieces, TetrioBag::new(123456).take(14).collect::<Vec<_>>());
+        assert_ne!(pieces, TetrioBag::new(654321).take(14).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn jstris_bag() {
+        let pieces: Vec<_> = JstrisBag::new("seed").take(14).collect();
+        assert_bags(&pieces);
+        assert_eq!(pieces, JstrisBag::new("seed").take(14).collect::<Vec<_>>());
+        assert_ne!(pieces, JstrisBag::new("other").take(14).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn memoryless() {
+        let pieces: Vec<_> = Memoryless::new(1).take(100).collect();
+        assert_eq!(pieces, Memoryless::new(1).take(100).collect::<Vec<_>>());
+        // all seven pieces come up, and not in bags
+        let mut kinds = pieces.clone();
+        kinds.sort_by_key(|&p| p as u8);
+        kinds.dedup();
+        assert_eq!(kinds.len(), 7);
+        assert!(pieces.windows(2).any(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn history() {
+        for seed in 0..20 {
+            let first = History::tgm(seed).next().unwrap();
+            assert!(![PieceType::S, PieceType::Z, PieceType::O].contains(&first));
+        }
+        let pieces: Vec<_> = History::tgm2(7).take(100).collect();
+        assert_eq!(pieces, History::tgm2(7).take(100).collect::<Vec<_>>());
+        // repeats are much rarer than the one in seven of a memoryless randomizer
+        let repeats = pieces.windows(2).filter(|pair| pair[0] == pair[1]).count();
+        assert!(repeats < 5, "{}", repeats);
+    }
+}
@@ -0,0 +1,317 @@
+//! Playing games from their inputs, recording a page for each piece placed.
+//!
+//! This is the part of replay import that is the same between games: the format-specific modules
+//! turn their replay's events into calls on a [`Game`], and decide when things like gravity and
+//! auto-shift happen.
+
+use std::collections::VecDeque;
+
+use crate::coordinates;
+use crate::quiz::Quiz;
+use crate::srs::{KickTable, Rotation};
+use crate::{CellColor, Fumen, Page, Piece, PieceType};
+
+/// The height of the playfield, including the rows above the visible field.
+const BOARD_HEIGHT: usize = 40;
+
+/// How garbage that is received is shown on the pages.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum GarbageDisplay {
+    /// Garbage rows rise using the page's `rise` flag and garbage row, one page per row.
+    Rise,
+    /// Garbage rows are part of the next page's field.
+    Baked
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Settings {
+    pub kicks: KickTable,
+    /// The number of next pieces written in each page's quiz comment.
+    pub previews: usize,
+    pub garbage: GarbageDisplay
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            kicks: KickTable::Srs,
+            previews: 5,
+            garbage: GarbageDisplay::Rise
+        }
+    }
+}
+
+/// A game being played, recording a page for each piece that locks.
+///
+/// Each page shows the field before its piece locked, with the piece in the position it locked
+/// in. Its comment is a quiz comment with the hold, current piece and previews as they were when
+/// the piece spawned, so it says which pieces were available to place. Pieces that lock above
+/// the 23 rows fumen can show are not shown.
+pub struct Game<Q> {
+    settings: Settings,
+    board: Vec<[CellColor; 10]>,
+    queue: Q,
+    next: VecDeque<PieceType>,
+    current: Option<Piece>,
+    hold: Option<PieceType>,
+    hold_used: bool,
+    quiz: Quiz,
+    garbage: Vec<[CellColor; 10]>,
+    pages: Vec<Page>,
+    placed: usize
+}
+
+impl<Q: Iterator<Item = PieceType>> Game<Q> {
+    /// Starts a game with pieces from `queue`, spawning the first piece.
+    pub fn new(settings: Settings, queue: Q) -> Game<Q> {
+        let mut game = Game {
+            settings,
+            board: vec![[CellColor::Empty; 10]; BOARD_HEIGHT],
+            queue,
+            next: VecDeque::new(),
+            current: None,
+            hold: None,
+            hold_used: false,
+            quiz: Quiz::default(),
+            garbage: vec![],
+            pages: vec![],
+            placed: 0
+        };
+        game.start_piece();
+        game
+    }
+
+    /// The falling piece, or `None` once the game has ended.
+    pub fn piece(&self) -> Option<Piece> {
+        self.current
+    }
+
+    /// Whether the game has ended, either by topping out or running out of pieces.
+    pub fn ended(&self) -> bool {
+        self.current.is_none()
+    }
+
+    /// The number of pieces that have locked.
+    pub fn pieces_placed(&self) -> usize {
+        self.placed
+    }
+
+    /// Moves the piece sideways by one cell, returning whether it moved.
+    pub fn shift(&mut self, dx: i32) -> bool {
+        self.try_move(|piece, board| piece.shifted(dx, 0, board))
+    }
+
+    /// Moves the piece sideways as far as it goes, returning whether it moved.
+    pub fn shift_fully(&mut self, dx: i32) -> bool {
+        let mut moved = false;
+        while self.shift(dx) {
+            moved = true;
+        }
+        moved
+    }
+
+    /// Moves the piece down by up to `cells` cells, returning whether it moved.
+    pub fn fall(&mut self, cells: u32) -> bool {
+        let mut moved = false;
+        for _ in 0..cells {
+            if !self.try_move(|piece, board| piece.shifted(0, -1, board)) {
+                break;
+            }
+            moved = true;
+        }
+        moved
+    }
+
+    /// Moves the piece as far down as it goes, returning whether it moved.
+    pub fn sonic_drop(&mut self) -> bool {
+        self.fall(BOARD_HEIGHT as u32)
+    }
+
+    pub fn rotate(&mut self, rotation: Rotation) -> bool {
+        let kicks = self.settings.kicks;
+        self.try_move(|piece, board| piece.rotated(rotation, board, kicks))
+    }
+
+    /// Whether the piece can't move down any further.
+    pub fn on_ground(&self) -> bool {
+        self.current.is_some_and(|piece| piece.on_ground(&self.board))
+    }
+
+    pub fn hard_drop(&mut self) {
+        self.sonic_drop();
+        self.lock();
+    }
+
+    /// Swaps the piece with the held piece, unless hold has been used since the last lock.
+    pub fn hold(&mut self) -> bool {
+        let current = match self.current {
+            Some(piece) if !self.hold_used => piece.kind,
+            _ => return false
+        };
+        let next = match self.hold.replace(current) {
+            Some(held) => Some(held),
+            None => self.next_piece()
+        };
+        self.spawn(next);
+        self.hold_used = true;
+        true
+    }
+
+    /// Adds rows of garbage with a hole in `column`.
+    ///
+    /// The rows are added after the next piece locks without clearing lines, since that is
+    /// when garbage waiting to be received goes into the field.
+    pub fn add_garbage(&mut self, lines: usize, column: usize) {
+        let mut row = [CellColor::Grey; 10];
+        if let Some(hole) = row.get_mut(column) {
+            *hole = CellColor::Empty;
+        }
+        self.garbage.resize(self.garbage.len() + lines, row);
+    }
+
+    /// Locks the piece where it is and spawns the next one.
+    pub fn lock(&mut self) {
+        let piece = match self.current {
+            Some(piece) => piece,
+            None => return
+        };
+        let mut page = Page {
+            piece: Some(piece),
+            comment: Some(self.quiz.to_string()),
+            ..Page::default()
+        };
+        page.field.copy_from_slice(&self.board[..23]);
+        let visible = piece.cells().iter().all(|&(_, y)| y < 23);
+        if !visible {
+            page.piece = None;
+        }
+
+        for &(x, y) in &piece.cells() {
+            self.board[y as usize][x as usize] = piece.kind.into();
+        }
+        let height = self.board.len();
+        self.board.retain(|row| row.contains(&CellColor::Empty));
+        let cleared = height - self.board.len();
+        self.board.resize(height, [CellColor::Empty; 10]);
+
+        let garbage = if cleared == 0 {
+            std::mem::take(&mut self.garbage)
+        } else {
+            vec![]
+        };
+        let mut rising = vec![];
+        for &row in &garbage {
+            self.board.insert(0, row);
+            self.board.pop();
+            if self.settings.garbage == GarbageDisplay::Rise && visible {
+                rising.push(row);
+            }
+        }
+        if let Some((&first, rest)) = rising.split_first() {
+            page.rise = true;
+            page.garbage_row = first;
+            self.pages.push(page);
+            for &row in rest {
+                self.pages.push(Page {
+                    rise: true,
+                    garbage_row: row,
+                    ..self.pages.last().unwrap().next_page()
+                });
+            }
+        } else {
+            self.pages.push(page);
+        }
+
+        self.hold_used = false;
+        self.placed += 1;
+        self.start_piece();
+    }
+
+    /// The fumen with a page for each piece locked so far.
+    pub fn fumen(&self) -> Fumen {
+        Fumen { pages: self.pages.clone(), source: None }
+    }
+
+    fn try_move(&mut self, f: impl FnOnce(&Piece, &[[CellColor; 10]]) -> Option<Piece>) -> bool {
+        match self.current.and_then(|piece| f(&piece, &self.board)) {
+            Some(piece) => {
+                self.current = Some(piece);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Spawns the next piece from the queue, as happens after a piece locks.
+    fn start_piece(&mut self) {
+        let kind = self.next_piece();
+        self.quiz = Quiz {
+            hold: self.hold,
+            current: kind,
+            next: self.next.iter().copied().take(self.settings.previews).collect()
+        };
+        self.spawn(kind);
+    }
+
+    fn next_piece(&mut self) -> Option<PieceType> {
+        while self.next.len() <= self.settings.previews {
+            match self.queue.next() {
+                Some(piece) => self.next.push_back(piece),
+                None => break
+            }
+        }
+        self.next.pop_front()
+    }
+
+    fn spawn(&mut self, kind: Option<PieceType>) {
+        self.current = kind
+            .map(coordinates::spawn)
+            .filter(|piece| piece.fits(&self.board));
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParseReplayError;
+
+impl std::fmt::Display for ParseReplayError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "the data is not a replay that can be read")
+    }
+}
+
+impl std::error::Error for ParseReplayError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::replay::*;
+
+    #[test]
+    fn pages() {
+        let queue = vec![PieceType::I, PieceType::T, PieceType::O, PieceType::S];
+        let settings = Settings { previews: 2, ..Settings::default() };
+        let mut game = Game::new(settings, queue.into_iter());
+        game.shift_fully(-1);
+        game.hard_drop();
+        game.hold();
+        game.shift_fully(1);
+        game.hard_drop();
+        game.add_garbage(2, 0);
+        game.hard_drop();
+        assert!(game.ended());
+
+        let fumen = game.fumen();
+        let pages = fumen.get_pages();
+        assert_eq!(pages.len(), 4);
+        assert_eq!(pages[0].comment(), Some("#Q=[](I)TO".to_owned()));
+        assert_eq!(pages[1].comment(), Some("#Q=[](T)OS".to_owned()));
+        assert_eq!(pages[1].piece.unwrap().kind, PieceType::O);
+        assert_eq!(pages[2].comment(), Some("#Q=[T](S)".to_owned()));
+        assert!(pages[2].rise && pages[3].rise);
+        assert_eq!(pages[3].piece, None);
+        assert_eq!(pages[3].get_field()[1][0], CellColor::I);
+        for pair in pages.windows(2) {
+            assert_eq!(pair[0].next_page().get_field(), pair[1].get_field());
+        }
+    }
+}
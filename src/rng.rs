@@ -0,0 +1,120 @@
+//! Random number generators, reproducing the ones games use.
+
+/// TETR.IO's random number generator, a Park-Miller generator.
+pub(crate) struct ParkMiller(i64);
+
+impl ParkMiller {
+    pub(crate) fn new(seed: f64) -> ParkMiller {
+        let mut seed = seed as i64 % 2147483647;
+        if seed <= 0 {
+            seed += 2147483646;
+        }
+        ParkMiller(seed)
+    }
+
+    /// A number from 0 up to but not including 1.
+    pub(crate) fn next_float(&mut self) -> f64 {
+        self.0 = self.0 * 16807 % 2147483647;
+        (self.0 - 1) as f64 / 2147483646.0
+    }
+
+    /// A number from 0 up to but not including `n`.
+    pub(crate) fn next_below(&mut self, n: usize) -> usize {
+        (self.next_float() * n as f64) as usize
+    }
+}
+
+/// The alea random number generator Jstris uses.
+pub(crate) struct Alea {
+    s0: f64,
+    s1: f64,
+    s2: f64,
+    c: f64
+}
+
+impl Alea {
+    pub(crate) fn new(seed: &str) -> Alea {
+        let mut mash = Mash(0xefc8249d_u32 as f64);
+        let mut alea = Alea { s0: mash.mash(" "), s1: mash.mash(" "), s2: mash.mash(" "), c: 1.0 };
+        alea.s0 -= mash.mash(seed);
+        if alea.s0 < 0.0 {
+            alea.s0 += 1.0;
+        }
+        alea.s1 -= mash.mash(seed);
+        if alea.s1 < 0.0 {
+            alea.s1 += 1.0;
+        }
+        alea.s2 -= mash.mash(seed);
+        if alea.s2 < 0.0 {
+            alea.s2 += 1.0;
+        }
+        alea
+    }
+
+    /// A number from 0 up to but not including 1.
+    pub(crate) fn next(&mut self) -> f64 {
+        let t = 2091639.0 * self.s0 + self.c * 2.3283064365386963e-10;
+        self.s0 = self.s1;
+        self.s1 = self.s2;
+        self.c = t.trunc();
+        self.s2 = t - self.c;
+        self.s2
+    }
+}
+
+struct Mash(f64);
+
+impl Mash {
+    fn mash(&mut self, data: &str) -> f64 {
+        // the arithmetic is done in doubles, truncated to 32 bits where javascript uses `>>> 0`
+        let to_u32 = |v: f64| (v.trunc() % 4294967296.0 + 4294967296.0) % 4294967296.0;
+        for unit in data.encode_utf16() {
+            self.0 += unit as f64;
+            let mut h = 0.02519603282416938 * self.0;
+            self.0 = to_u32(h);
+            h -= self.0;
+            h *= self.0;
+            self.0 = to_u32(h);
+            h -= self.0;
+            self.0 += h * 4294967296.0;
+        }
+        to_u32(self.0) * 2.3283064365386963e-10
+    }
+}
+
+/// The linear congruential generator the Tetris: The Grand Master games use.
+pub(crate) struct Tgm(u32);
+
+impl Tgm {
+    pub(crate) fn new(seed: u32) -> Tgm {
+        Tgm(seed)
+    }
+
+    /// A number from 0 up to but not including 32768.
+    pub(crate) fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(0x41c64e6d).wrapping_add(12345);
+        self.0 >> 10 & 0x7fff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rng::*;
+
+    #[test]
+    fn alea() {
+        let mut a = Alea::new("seed");
+        let values: Vec<_> = (0..10).map(|_| a.next()).collect();
+        assert!(values.iter().all(|&v| (0.0..1.0).contains(&v)));
+        let mut b = Alea::new("seed");
+        assert!(values.iter().all(|&v| v == b.next()));
+        assert_ne!(Alea::new("other").next(), values[0]);
+    }
+
+    #[test]
+    fn tgm() {
+        let mut rng = Tgm::new(0);
+        assert_eq!(rng.next(), 12345 >> 10);
+        assert!((0..100).all(|_| rng.next() < 32768));
+    }
+}
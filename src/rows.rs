@@ -0,0 +1,160 @@
+//! Fields as rows of cell strings for the structured representation.
+//!
+//! Each row is 10 characters: `_` for empty cells, `X` for grey cells, and piece letters for
+//! coloured cells. Fields are written from the top, starting at the highest row with a filled
+//! cell, and are read bottom-aligned, so any number of rows up to 23 may be given.
+
+#[cfg(feature = "serde")]
+use std::convert::TryFrom;
+
+#[cfg(feature = "serde")]
+use serde::de::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serializer};
+
+use crate::CellColor;
+#[cfg(feature = "serde")]
+use crate::{Piece, PieceType, RotationState};
+
+/// The rows of a field from the top, starting at the highest row with a filled cell.
+pub fn field_rows(field: &[[CellColor; 10]; 23]) -> impl Iterator<Item = String> + '_ {
+    let height = field.iter().rposition(|row| *row != [CellColor::Empty; 10]).map_or(0, |y| y + 1);
+    field[..height].iter().rev().map(row_string)
+}
+
+#[cfg(feature = "serde")]
+pub fn serialize<S: Serializer>(field: &[[CellColor; 10]; 23], ser: S) -> Result<S::Ok, S::Error> {
+    ser.collect_seq(field_rows(field))
+}
+
+#[cfg(feature = "serde")]
+pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<[[CellColor; 10]; 23], D::Error> {
+    let rows = Vec::<String>::deserialize(de)?;
+    if rows.len() > 23 {
+        return Err(D::Error::invalid_length(rows.len(), &"at most 23 rows"));
+    }
+    let mut field = [[CellColor::Empty; 10]; 23];
+    for (y, row) in rows.iter().rev().enumerate() {
+        field[y] = parse_row(row).map_err(D::Error::custom)?;
+    }
+    Ok(field)
+}
+
+/// A single row, for the garbage row.
+#[cfg(feature = "serde")]
+pub mod row {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(row: &[CellColor; 10], ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(&row_string(row))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<[CellColor; 10], D::Error> {
+        parse_row(&String::deserialize(de)?).map_err(D::Error::custom)
+    }
+}
+
+pub fn row_string(row: &[CellColor; 10]) -> String {
+    row.iter().map(|&cell| match cell {
+        CellColor::Empty => '_',
+        CellColor::Grey => 'X',
+        CellColor::I => 'I',
+        CellColor::L => 'L',
+        CellColor::O => 'O',
+        CellColor::Z => 'Z',
+        CellColor::T => 'T',
+        CellColor::J => 'J',
+        CellColor::S => 'S'
+    }).collect()
+}
+
+/// The fields of a [`Piece`], which are only a piece once they are checked to be in the field.
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+pub struct PieceFields {
+    kind: PieceType,
+    rotation: RotationState,
+    x: u32,
+    y: u32
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<PieceFields> for Piece {
+    type Error = String;
+
+    fn try_from(fields: PieceFields) -> Result<Piece, String> {
+        let PieceFields { kind, rotation, x, y } = fields;
+        let piece = Piece { kind, rotation, x, y };
+        // fumen can't show pieces that stick out of the field
+        if !piece.in_field() {
+            return Err(format!("{:?} is not in the field", piece));
+        }
+        Ok(piece)
+    }
+}
+
+#[cfg(feature = "serde")]
+fn parse_row(s: &str) -> Result<[CellColor; 10], String> {
+    let mut row = [CellColor::Empty; 10];
+    if s.chars().count() != 10 {
+        return Err(format!("row {:?} does not have 10 cells", s));
+    }
+    for (cell, c) in row.iter_mut().zip(s.chars()) {
+        *cell = match c {
+            '_' => CellColor::Empty,
+            'X' => CellColor::Grey,
+            c => PieceType::from_char(c)
+                .ok_or_else(|| format!("{:?} is not a cell in row {:?}", c, s))?
+                .into()
+        };
+    }
+    Ok(row)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn structured() {
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).field[0] = [CellColor::Grey; 10];
+            (*page).field[1][0] = CellColor::T;
+            (*page).piece = Some(Piece {
+                kind: PieceType::T,
+                rotation: RotationState::North,
+                x: 4,
+                y: 2
+            });
+            (*page).set_comment_rs(Some("hi".to_owned()));
+        }
+        let json = serde_json::to_value(&fumen).unwrap();
+        assert_eq!(json, serde_json::json!({"pages": [{
+            "piece": {"kind": "T", "rotation": "North", "x": 4, "y": 2},
+            "rise": false,
+            "mirror": false,
+            "lock": true,
+            "guideline_switch": false,
+            "comment": "hi",
+            "field": ["T_________", "XXXXXXXXXX"],
+            "garbage_row": "__________"
+        }]}));
+        assert_eq!(serde_json::from_value::<Fumen>(json).unwrap(), fumen);
+
+        let minimal = serde_json::json!({"pages": [{"field": ["IIII______"]}]});
+        let fumen: Fumen = serde_json::from_value(minimal).unwrap();
+        assert_eq!(fumen.get_pages()[0].get_field()[0][3], CellColor::I);
+        assert!(fumen.get_pages()[0].lock);
+        let bad = serde_json::json!({"pages": [{"field": ["IIII_____"]}]});
+        assert!(serde_json::from_value::<Fumen>(bad).is_err());
+        // pieces sticking out of the field would make encoding panic
+        for (x, y) in [(0, 5), (9, 5), (4, 22), (40, 5)] {
+            let outside = serde_json::json!({"pages": [{
+                "piece": {"kind": "T", "rotation": "North", "x": x, "y": y}
+            }]});
+            assert!(serde_json::from_value::<Fumen>(outside).is_err(), "{} {}", x, y);
+        }
+    }
+}
@@ -0,0 +1,272 @@
+//! JSON Schema and TypeScript definitions for the structured representation of a fumen.
+//!
+//! The structured representation is what the `serde` feature serializes a [`Fumen`] as, and what
+//! the wasm `Fumen.pages` getter returns. The TypeScript definitions are also included in the
+//! wasm bindings' generated `.d.ts`.
+
+use js_sys::{Array, Object, Reflect};
+use wasm_bindgen::prelude::*;
+
+use crate::rows::{field_rows, row_string};
+use crate::{Fumen, Page, Piece, RotationState};
+
+/// JSON Schema (draft 2020-12) for a serialized [`Fumen`].
+///
+/// The schema is written by hand, and the tests validate what serde writes against it. It bounds
+/// piece centres to the field, but can't say that every cell of a piece must be in the field too;
+/// deserializing checks that separately.
+pub const JSON_SCHEMA: &str = r##"{
+  "$schema": "https://json-schema.org/draft/2020-12/schema",
+  "title": "Fumen",
+  "type": "object",
+  "properties": {
+    "pages": { "type": "array", "items": { "$ref": "#/$defs/Page" } }
+  },
+  "required": ["pages"],
+  "additionalProperties": false,
+  "$defs": {
+    "Page": {
+      "type": "object",
+      "properties": {
+        "piece": { "oneOf": [{ "$ref": "#/$defs/Piece" }, { "type": "null" }] },
+        "rise": { "type": "boolean", "default": false },
+        "mirror": { "type": "boolean", "default": false },
+        "lock": { "type": "boolean", "default": true },
+        "guideline_switch": { "type": "boolean", "default": false },
+        "comment": { "type": ["string", "null"], "default": null },
+        "field": {
+          "description": "Rows from the top, aligned to the bottom of the 23 row field.",
+          "type": "array",
+          "items": { "$ref": "#/$defs/Row" },
+          "maxItems": 23
+        },
+        "garbage_row": { "$ref": "#/$defs/Row" }
+      },
+      "additionalProperties": false
+    },
+    "Piece": {
+      "description": "Every cell must also be in the field, which deserializing checks.",
+      "type": "object",
+      "properties": {
+        "kind": { "$ref": "#/$defs/PieceType" },
+        "rotation": { "$ref": "#/$defs/RotationState" },
+        "x": { "type": "integer", "minimum": 0, "maximum": 9 },
+        "y": { "type": "integer", "minimum": 0, "maximum": 22, "description": "y-up" }
+      },
+      "required": ["kind", "rotation", "x", "y"],
+      "additionalProperties": false
+    },
+    "PieceType": { "enum": ["I", "L", "O", "Z", "T", "J", "S"] },
+    "RotationState": { "enum": ["South", "East", "North", "West"] },
+    "CellColor": { "enum": ["Empty", "I", "L", "O", "Z", "T", "J", "S", "Grey"] },
+    "Row": {
+      "description": "10 cells: _ for empty, X for grey, or a piece letter.",
+      "type": "string",
+      "pattern": "^[_XILOZTJS]{10}$"
+    }
+  }
+}
+"##;
+
+// the custom section must be a string literal, so the definitions are written through a macro
+// to also make them available to rust
+macro_rules! typescript {
+    ($definitions:literal) => {
+        #[wasm_bindgen(typescript_custom_section)]
+        const TYPESCRIPT_SECTION: &str = $definitions;
+
+        /// TypeScript definitions for the structured representation.
+        ///
+        /// The names end in `Json` so they don't clash with the wasm classes and enums.
+        pub const TYPESCRIPT: &str = $definitions;
+    };
+}
+
+typescript!(r#"
+export type PieceTypeJson = "I" | "L" | "O" | "Z" | "T" | "J" | "S";
+export type RotationStateJson = "South" | "East" | "North" | "West";
+export type CellColorJson = "Empty" | PieceTypeJson | "Grey";
+
+export interface PieceJson {
+    kind: PieceTypeJson;
+    rotation: RotationStateJson;
+    x: number;
+    /** y-up */
+    y: number;
+}
+
+export interface PageJson {
+    piece: PieceJson | null;
+    rise: boolean;
+    mirror: boolean;
+    lock: boolean;
+    guideline_switch: boolean;
+    comment: string | null;
+    /** Rows from the top, aligned to the bottom of the 23 row field. */
+    field: string[];
+    garbage_row: string;
+}
+
+export interface FumenJson {
+    pages: PageJson[];
+}
+"#);
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "PageJson[]")]
+    pub type PageJsonArray;
+}
+
+#[wasm_bindgen]
+impl Fumen {
+    /// The pages in their structured representation.
+    #[wasm_bindgen(getter)]
+    pub fn pages(&self) -> PageJsonArray {
+        let array = Array::new();
+        for page in &self.pages {
+            array.push(&page_object(page));
+        }
+        array.unchecked_into()
+    }
+}
+
+fn page_object(page: &Page) -> JsValue {
+    let object = Object::new();
+    let set = |key: &str, value: JsValue| {
+        Reflect::set(&object, &key.into(), &value).unwrap();
+    };
+    set("piece", page.piece.map_or(JsValue::NULL, piece_object));
+    set("rise", page.rise.into());
+    set("mirror", page.mirror.into());
+    set("lock", page.lock.into());
+    set("guideline_switch", page.guideline_switch.into());
+    set("comment", page.comment.as_deref().map_or(JsValue::NULL, JsValue::from));
+    set("field", field_rows(&page.field).map(JsValue::from).collect::<Array>().into());
+    set("garbage_row", row_string(&page.garbage_row).into());
+    object.into()
+}
+
+fn piece_object(piece: Piece) -> JsValue {
+    let object = Object::new();
+    let set = |key: &str, value: JsValue| {
+        Reflect::set(&object, &key.into(), &value).unwrap();
+    };
+    set("kind", piece.kind.to_char().to_string().into());
+    set("rotation", match piece.rotation {
+        RotationState::South => "South",
+        RotationState::East => "East",
+        RotationState::North => "North",
+        RotationState::West => "West"
+    }.into());
+    set("x", piece.x.into());
+    set("y", piece.y.into());
+    object.into()
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use crate::*;
+    use crate::schema::*;
+
+    /// Checks the schema's properties against what serde actually writes.
+    #[test]
+    fn schema_matches_serde() {
+        let schema: serde_json::Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).piece = Some(Piece {
+                kind: PieceType::T,
+                rotation: RotationState::North,
+                x: 4,
+                y: 0
+            });
+        }
+        let json = serde_json::to_value(&fumen).unwrap();
+
+        let keys = |value: &serde_json::Value| {
+            let mut keys: Vec<_> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+        let defs = &schema["$defs"];
+        assert_eq!(keys(&json), keys(&schema["properties"]));
+        assert_eq!(keys(&json["pages"][0]), keys(&defs["Page"]["properties"]));
+        assert_eq!(keys(&json["pages"][0]["piece"]), keys(&defs["Piece"]["properties"]));
+
+        for interface in &["PieceJson", "PageJson", "FumenJson"] {
+            assert!(TYPESCRIPT.contains(&format!("export interface {} {{", interface)));
+        }
+        for key in keys(&defs["Page"]["properties"]) {
+            assert!(TYPESCRIPT.contains(&format!("    {}: ", key)));
+        }
+    }
+
+    /// Checks serialized fumens using every piece, rotation and colour against the schema, and the
+    /// schema's enums against the TypeScript unions.
+    #[test]
+    fn schema_validates_serde() {
+        let schema: serde_json::Value = serde_json::from_str(JSON_SCHEMA).unwrap();
+        let validator = jsonschema::JSONSchema::options()
+            .with_draft(jsonschema::Draft::Draft202012)
+            .compile(&schema)
+            .unwrap();
+
+        use PieceType::*;
+        let rotations = [
+            RotationState::South, RotationState::East, RotationState::North, RotationState::West
+        ];
+        let mut fumen = Fumen::default();
+        for &kind in &[I, L, O, Z, T, J, S] {
+            for &rotation in &rotations {
+                let page = fumen.add_page();
+                unsafe {
+                    (*page).piece = Some(Piece { kind, rotation, x: 4, y: 5 });
+                    (*page).rise = true;
+                    (*page).guideline_switch = true;
+                }
+            }
+        }
+        let last = fumen.pages.last_mut().unwrap();
+        last.comment = Some("comment".to_owned());
+        last.lock = false;
+        for (x, &cell) in [
+            CellColor::Empty, CellColor::I, CellColor::L, CellColor::O, CellColor::Z,
+            CellColor::T, CellColor::J, CellColor::S, CellColor::Grey
+        ].iter().enumerate() {
+            last.field[0][x] = cell;
+            last.garbage_row[x] = cell;
+        }
+        fumen.add_page();
+
+        let json = serde_json::to_value(&fumen).unwrap();
+        if let Err(errors) = validator.validate(&json) {
+            let errors: Vec<_> = errors.map(|error| error.to_string()).collect();
+            panic!("{:?}", errors);
+        }
+        let mut invalid = json.clone();
+        invalid["pages"][0]["piece"]["rotation"] = "Up".into();
+        assert!(!validator.is_valid(&invalid));
+        let mut invalid = json.clone();
+        invalid["pages"][0]["field"] = serde_json::json!(["XXXXXXXXX"]);
+        assert!(!validator.is_valid(&invalid));
+        for &(x, y) in &[(10, 5), (4, 23)] {
+            let mut invalid = json.clone();
+            invalid["pages"][0]["piece"]["x"] = x.into();
+            invalid["pages"][0]["piece"]["y"] = y.into();
+            assert!(!validator.is_valid(&invalid), "{} {}", x, y);
+            assert!(serde_json::from_value::<Fumen>(invalid).is_err());
+        }
+
+        let defs = &schema["$defs"];
+        let unions = [("PieceTypeJson", "PieceType"), ("RotationStateJson", "RotationState")];
+        for &(name, def) in &unions {
+            let variants: Vec<_> = defs[def]["enum"].as_array().unwrap().iter()
+                .map(|variant| variant.to_string())
+                .collect();
+            let union = format!("export type {} = {};", name, variants.join(" | "));
+            assert!(TYPESCRIPT.contains(&union), "{}", union);
+        }
+    }
+}
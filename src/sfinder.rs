@@ -0,0 +1,236 @@
+//! Conversion to and from the field text used by [solution-finder].
+//!
+//! A field file has an optional first line giving the maximum number of lines to clear,
+//! followed by rows of 10 cells from top to bottom, with `_` for empty cells and `X` for filled
+//! ones. Lines starting with `#` are comments. Instead of rows, the first line may be a fumen,
+//! in which case its first page is used, the same way as `--tetfu` without `--page`.
+//!
+//! [solution-finder]: https://github.com/knewjade/solution-finder
+
+use crate::{CellColor, Fumen, Page};
+
+/// The number of lines solution-finder clears by default, and so the smallest height written.
+const DEFAULT_HEIGHT: usize = 4;
+
+impl Page {
+    /// Parses solution-finder field text, returning the page and the number of lines to clear.
+    ///
+    /// Comment lines become the page comment, joined with newlines. Cells may also be written
+    /// as piece letters (`IJLOSTZ`), which keep their colour; `X` is read as grey. Without a
+    /// height line, the number of lines to clear is solution-finder's default of 4.
+    pub fn from_sfinder_field(text: &str) -> Result<(Page, usize), ParseSfinderFieldError> {
+        let mut page = Page::default();
+        let mut comment: Option<String> = None;
+        let mut rows = vec![];
+        let mut height = None;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = ParseSfinderFieldError { line: i + 1 };
+            if let Some(text) = line.strip_prefix('#') {
+                let text = text.strip_prefix(' ').unwrap_or(text);
+                match comment {
+                    Some(ref mut comment) => {
+                        comment.push('\n');
+                        comment.push_str(text);
+                    }
+                    None => comment = Some(text.to_owned())
+                }
+            } else if line.is_empty() {
+                continue;
+            } else if rows.is_empty() && height.is_none() && line.starts_with("v115@") {
+                let fumen = Fumen::decode(line).map_err(|_| error)?;
+                page = fumen.sfinder_page(1).ok_or(error)?;
+                height = Some(DEFAULT_HEIGHT);
+            } else if rows.is_empty() && height.is_none() && line.parse::<usize>().is_ok() {
+                height = line.parse().ok();
+            } else {
+                let mut row = [CellColor::Empty; 10];
+                if line.chars().count() != 10 || rows.len() == 23 {
+                    return Err(error);
+                }
+                for (cell, c) in row.iter_mut().zip(line.chars()) {
+                    *cell = match c {
+                        '_' => CellColor::Empty,
+                        'X' => CellColor::Grey,
+                        'I' => CellColor::I,
+                        'L' => CellColor::L,
+                        'O' => CellColor::O,
+                        'Z' => CellColor::Z,
+                        'T' => CellColor::T,
+                        'J' => CellColor::J,
+                        'S' => CellColor::S,
+                        _ => return Err(error)
+                    };
+                }
+                rows.push(row);
+            }
+        }
+        for (y, row) in rows.iter().rev().enumerate() {
+            page.field[y] = *row;
+        }
+        if comment.is_some() {
+            page.comment = comment;
+        }
+        Ok((page, height.unwrap_or(DEFAULT_HEIGHT)))
+    }
+
+    /// Writes the page as solution-finder field text, with `height` lines to clear.
+    ///
+    /// Rows are written up to `height` or the highest filled cell, whichever is higher. Grey
+    /// cells are written as `X` and coloured cells as the letter of their piece, so pages of a
+    /// fumen should be taken from [`Fumen::sfinder_page`] to write monochrome pages in grey. Each
+    /// line of the page comment is written as a comment line before the field.
+    pub fn to_sfinder_field(&self, height: usize) -> String {
+        let rows = self.field.iter()
+            .rposition(|row| row.iter().any(|&c| c != CellColor::Empty))
+            .map_or(0, |y| y + 1)
+            .max(height.min(23));
+        let mut text = String::new();
+        if let Some(ref comment) = self.comment {
+            for line in comment.lines() {
+                text.push_str("# ");
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        text.push_str(&height.to_string());
+        text.push('\n');
+        for row in self.field[..rows].iter().rev() {
+            for &cell in row {
+                text.push(match cell {
+                    CellColor::Empty => '_',
+                    CellColor::Grey => 'X',
+                    CellColor::I => 'I',
+                    CellColor::L => 'L',
+                    CellColor::O => 'O',
+                    CellColor::Z => 'Z',
+                    CellColor::T => 'T',
+                    CellColor::J => 'J',
+                    CellColor::S => 'S'
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Fumen {
+    /// The page solution-finder uses for `--tetfu` with `--page <page>`.
+    ///
+    /// Pages are numbered from 1. If the page's piece is locked, solution-finder places it
+    /// before solving, so it is placed into the field here and the page is left without a piece;
+    /// no lines are cleared. If the page has no comment of its own, it gets the comment shown on
+    /// it by the fumen editor, since solution-finder reads its pattern from there when none is
+    /// given. Pages shown in monochrome have their filled cells greyed, as they are shown.
+    pub fn sfinder_page(&self, page: usize) -> Option<Page> {
+        let index = page.checked_sub(1)?;
+        let mut result = self.page_as_shown(index)?;
+        if let Some(piece) = result.piece.filter(|_| result.lock) {
+            let color = if self.guideline_at(index) { piece.kind.into() } else { CellColor::Grey };
+            for (x, y) in piece.cells() {
+                result.field[y as usize][x as usize] = color;
+            }
+            result.piece = None;
+        }
+        if result.comment.is_none() {
+            result.comment = self.pages[..index].iter().rev().find_map(|p| p.comment.clone());
+        }
+        Some(result)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParseSfinderFieldError {
+    /// 1-based number of the line that could not be parsed.
+    pub line: usize
+}
+
+impl std::fmt::Display for ParseSfinderFieldError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "line {} is not valid solution-finder field text", self.line)
+    }
+}
+
+impl std::error::Error for ParseSfinderFieldError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::sfinder::*;
+
+    #[test]
+    fn round_trip() {
+        let text = "# *p7\n4\nXX______XX\nXXX____XXX\nXXXX___XXX\nXXX____XXX\n";
+        let (page, height) = Page::from_sfinder_field(text).unwrap();
+        assert_eq!(height, 4);
+        assert_eq!(page.comment(), Some("*p7".to_owned()));
+        assert_eq!(page.field[3][1], CellColor::Grey);
+        assert_eq!(page.field[3][2], CellColor::Empty);
+        assert_eq!(page.field[0][3], CellColor::Empty);
+        assert_eq!(page.field[4], [CellColor::Empty; 10]);
+        assert_eq!(page.to_sfinder_field(height), text);
+
+        // the height is the number of lines to clear, not the height of the field
+        let text = format!("6\n{}XX______XX\n", "__________\n".repeat(5));
+        let (page, height) = Page::from_sfinder_field(&text).unwrap();
+        assert_eq!(height, 6);
+        assert_eq!(page.to_sfinder_field(height), text);
+    }
+
+    #[test]
+    fn colours_and_errors() {
+        let (page, height) = Page::from_sfinder_field("T_________\nTTIIII____").unwrap();
+        assert_eq!(height, 4);
+        assert_eq!(page.field[1][0], CellColor::T);
+        assert_eq!(page.field[0][2], CellColor::I);
+        assert_eq!(
+            page.to_sfinder_field(height),
+            "4\n__________\n__________\nT_________\nTTIIII____\n"
+        );
+        assert_eq!(
+            Page::from_sfinder_field("4\nXX______X\n"),
+            Err(ParseSfinderFieldError { line: 2 })
+        );
+        let text = format!("4\n# rows\n{}", "XXXXX_____\n".repeat(25));
+        assert_eq!(Page::from_sfinder_field(&text), Err(ParseSfinderFieldError { line: 26 }));
+    }
+
+    #[test]
+    fn tetfu_page() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.add_page();
+        fumen.pages[0].comment = Some("T,*p6".to_owned());
+        fumen.pages[1].field[0][0] = CellColor::Grey;
+        let page = fumen.sfinder_page(2).unwrap();
+        assert_eq!(page.comment(), Some("T,*p6".to_owned()));
+        assert_eq!(page.field[0][0], CellColor::Grey);
+        assert_eq!(fumen.sfinder_page(0), None);
+
+        // a locked piece is placed before solving
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 1 };
+        fumen.pages[1].piece = Some(t);
+        let page = fumen.sfinder_page(2).unwrap();
+        assert_eq!(page.piece, None);
+        assert_eq!(page.field[1][3..6], [CellColor::T; 3]);
+        assert_eq!(page.field[2][4], CellColor::T);
+        fumen.pages[1].lock = false;
+        let page = fumen.sfinder_page(2).unwrap();
+        assert_eq!(page.piece, Some(t));
+        assert_eq!(page.field[1], [CellColor::Empty; 10]);
+        fumen.pages[1].piece = None;
+
+        // monochrome pages are written in grey
+        fumen.pages[1].field[0][1] = CellColor::T;
+        assert!(fumen.set_guideline(false));
+        let page = fumen.sfinder_page(2).unwrap();
+        assert_eq!(page.field[0][1], CellColor::Grey);
+        assert!(page.to_sfinder_field(4).ends_with("XX________\n"));
+        assert_eq!(fumen.sfinder_page(3), None);
+
+        let (page, height) = Page::from_sfinder_field(&fumen.encode()).unwrap();
+        assert_eq!(page, fumen.pages[0]);
+        assert_eq!(height, 4);
+    }
+}
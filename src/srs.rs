@@ -0,0 +1,263 @@
+//! Piece movement and rotation using the Super Rotation System.
+//!
+//! Fields are given as rows of cells from the bottom up, and may be any height. Cells above
+//! the top of a field are empty, and cells outside its sides and bottom are solid.
+
+use std::collections::HashSet;
+
+use crate::{CellColor, Piece, PieceType, RotationState};
+
+/// How far up pieces are followed when searching for reachable positions, which is the height of
+/// the tallest field of the games replays are imported from.
+const SEARCH_HEIGHT: u32 = 40;
+
+/// A rotation input.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+    Half
+}
+
+/// The kick tables to use when rotating.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum KickTable {
+    /// Guideline SRS. Half rotations are allowed, but have no kicks.
+    Srs,
+    /// TETR.IO's SRS+, which has mirror-symmetric I kicks and half rotation kicks.
+    SrsPlus
+}
+
+impl RotationState {
+    pub fn rotate(self, rotation: Rotation) -> RotationState {
+        use RotationState::*;
+        match (self, rotation) {
+            (North, Rotation::Clockwise) | (South, Rotation::CounterClockwise) |
+            (West, Rotation::Half) => East,
+            (East, Rotation::Clockwise) | (West, Rotation::CounterClockwise) |
+            (North, Rotation::Half) => South,
+            (South, Rotation::Clockwise) | (North, Rotation::CounterClockwise) |
+            (East, Rotation::Half) => West,
+            (West, Rotation::Clockwise) | (East, Rotation::CounterClockwise) |
+            (South, Rotation::Half) => North
+        }
+    }
+}
+
+impl Piece {
+    /// Whether the piece is inside the field and does not overlap any filled cells.
+    pub fn fits(&self, field: &[[CellColor; 10]]) -> bool {
+        self.cells().iter().all(|&(x, y)| {
+            // rows above the field are empty
+            (0..10).contains(&x) && y >= 0 && match field.get(y as usize) {
+                Some(row) => row[x as usize] == CellColor::Empty,
+                None => true
+            }
+        })
+    }
+
+    /// The piece moved by `dx` and `dy`, if it fits there.
+    pub fn shifted(&self, dx: i32, dy: i32, field: &[[CellColor; 10]]) -> Option<Piece> {
+        let x = self.x as i32 + dx;
+        let y = self.y as i32 + dy;
+        if x < 0 || y < 0 {
+            return None;
+        }
+        let piece = Piece { x: x as u32, y: y as u32, ..*self };
+        if piece.fits(field) {
+            Some(piece)
+        } else {
+            None
+        }
+    }
+
+    /// The piece moved as far down as it can go.
+    pub fn dropped(&self, field: &[[CellColor; 10]]) -> Piece {
+        let mut piece = *self;
+        while let Some(p) = piece.shifted(0, -1, field) {
+            piece = p;
+        }
+        piece
+    }
+
+    /// Whether the piece can't move down.
+    pub fn on_ground(&self, field: &[[CellColor; 10]]) -> bool {
+        self.shifted(0, -1, field).is_none()
+    }
+
+    /// The piece after rotating it, trying each kick in turn.
+    ///
+    /// Returns `None` if none of the kicks fit.
+    pub fn rotated(
+        &self, rotation: Rotation, field: &[[CellColor; 10]], kicks: KickTable
+    ) -> Option<Piece> {
+        let to = self.rotation.rotate(rotation);
+        // the rotation centres of I and O pieces are cells, but SRS rotates them about the
+        // centre of their bounding box, so the centre moves
+        let (from_x, from_y) = box_offset(self.kind, self.rotation);
+        let (to_x, to_y) = box_offset(self.kind, to);
+        let rotated = Piece { rotation: to, ..*self };
+        kick_table(self.kind, self.rotation, to, kicks).iter().find_map(|&(kx, ky)| {
+            rotated.shifted(from_x - to_x + kx, from_y - to_y + ky, field)
+        })
+    }
+}
+
+/// Finds every position a piece can be moved to from `spawn` by shifting, soft dropping and
+/// rotating, including half rotations.
+///
+/// Returns no positions if `spawn` doesn't fit. Positions with their centre 40 or more rows up
+/// aren't searched.
+pub fn reachable(spawn: Piece, field: &[[CellColor; 10]], kicks: KickTable) -> HashSet<Piece> {
+    let mut seen = HashSet::new();
+    if !spawn.fits(field) {
+        return seen;
+    }
+    seen.insert(spawn);
+    let mut stack = vec![spawn];
+    while let Some(piece) = stack.pop() {
+        let moves = [
+            piece.shifted(-1, 0, field),
+            piece.shifted(1, 0, field),
+            piece.shifted(0, -1, field),
+            piece.rotated(Rotation::Clockwise, field, kicks),
+            piece.rotated(Rotation::CounterClockwise, field, kicks),
+            piece.rotated(Rotation::Half, field, kicks)
+        ];
+        for &next in moves.iter().flatten() {
+            if next.y < SEARCH_HEIGHT && seen.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+    seen
+}
+
+/// Offset from the piece's centre to the corner of its SRS bounding box.
+fn box_offset(kind: PieceType, rotation: RotationState) -> (i32, i32) {
+    match (kind, rotation) {
+        (PieceType::I, RotationState::North) => (-1, -2),
+        (PieceType::I, RotationState::East) => (-2, -2),
+        (PieceType::I, RotationState::South) => (-2, -1),
+        (PieceType::I, RotationState::West) => (-1, -1),
+        (PieceType::O, RotationState::North) => (0, 0),
+        (PieceType::O, RotationState::East) => (0, -1),
+        (PieceType::O, RotationState::South) => (-1, -1),
+        (PieceType::O, RotationState::West) => (-1, 0),
+        _ => (0, 0)
+    }
+}
+
+/// The kicks to try, in order, y-up.
+fn kick_table(
+    kind: PieceType, from: RotationState, to: RotationState, kicks: KickTable
+) -> &'static [(i32, i32)] {
+    use RotationState::*;
+    match (kind, kicks, from, to) {
+        (PieceType::O, _, _, _) => &[(0, 0)],
+
+        (PieceType::I, KickTable::Srs, North, East) => &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        (PieceType::I, KickTable::Srs, East, North) => &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        (PieceType::I, KickTable::Srs, East, South) => &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+        (PieceType::I, KickTable::Srs, South, East) => &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+        (PieceType::I, KickTable::Srs, South, West) => &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        (PieceType::I, KickTable::Srs, West, South) => &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        (PieceType::I, KickTable::Srs, West, North) => &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+        (PieceType::I, KickTable::Srs, North, West) => &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+
+        (PieceType::I, KickTable::SrsPlus, North, East) =>
+            &[(0, 0), (1, 0), (-2, 0), (-2, 1), (1, -2)],
+        (PieceType::I, KickTable::SrsPlus, East, North) =>
+            &[(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)],
+        (PieceType::I, KickTable::SrsPlus, East, South) =>
+            &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+        (PieceType::I, KickTable::SrsPlus, South, East) =>
+            &[(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)],
+        (PieceType::I, KickTable::SrsPlus, South, West) =>
+            &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        (PieceType::I, KickTable::SrsPlus, West, South) =>
+            &[(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)],
+        (PieceType::I, KickTable::SrsPlus, West, North) =>
+            &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+        (PieceType::I, KickTable::SrsPlus, North, West) =>
+            &[(0, 0), (-1, 0), (2, 0), (2, 1), (-1, -2)],
+        (PieceType::I, _, _, _) => &[(0, 0)],
+
+        (_, _, North, East) | (_, _, South, East) => &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+        (_, _, East, North) | (_, _, East, South) => &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        (_, _, South, West) | (_, _, North, West) => &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        (_, _, West, South) | (_, _, West, North) => &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+
+        (_, KickTable::SrsPlus, North, South) =>
+            &[(0, 0), (0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+        (_, KickTable::SrsPlus, East, West) => &[(0, 0), (1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+        (_, KickTable::SrsPlus, South, North) =>
+            &[(0, 0), (0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)],
+        (_, KickTable::SrsPlus, West, East) => &[(0, 0), (-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+        _ => &[(0, 0)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::srs::*;
+
+    #[test]
+    fn i_rotation_keeps_bounding_box() {
+        let field = [[CellColor::Empty; 10]; 23];
+        let i = Piece { kind: PieceType::I, rotation: RotationState::North, x: 4, y: 10 };
+        let mut cells = i.rotated(Rotation::Clockwise, &field, KickTable::Srs).unwrap().cells();
+        cells.sort();
+        assert_eq!(cells, [(5, 8), (5, 9), (5, 10), (5, 11)]);
+        let o = Piece { kind: PieceType::O, rotation: RotationState::North, x: 4, y: 10 };
+        let mut cells = o.rotated(Rotation::Half, &field, KickTable::Srs).unwrap().cells();
+        cells.sort();
+        assert_eq!(cells, [(4, 10), (4, 11), (5, 10), (5, 11)]);
+    }
+
+    #[test]
+    fn kicks() {
+        let field = [[CellColor::Empty; 10]; 23];
+        let t = Piece { kind: PieceType::T, rotation: RotationState::East, x: 0, y: 5 };
+        assert_eq!(
+            t.rotated(Rotation::CounterClockwise, &field, KickTable::Srs),
+            Some(Piece { kind: PieceType::T, rotation: RotationState::North, x: 1, y: 5 })
+        );
+
+        // a T-spin triple slot, which needs the last kick of the table
+        let mut field = [[CellColor::Grey; 10]; 23];
+        for row in &mut field[3..] {
+            *row = [CellColor::Empty; 10];
+        }
+        field[0][3] = CellColor::Empty;
+        field[1][3] = CellColor::Empty;
+        field[1][4] = CellColor::Empty;
+        field[2][3] = CellColor::Empty;
+        field[3][2] = CellColor::Grey;
+        field[4][3] = CellColor::Grey;
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 3 };
+        assert_eq!(t.dropped(&field), t);
+        let spun = t.rotated(Rotation::Clockwise, &field, KickTable::Srs).unwrap();
+        assert_eq!(spun, Piece { kind: PieceType::T, rotation: RotationState::East, x: 3, y: 1 });
+        assert!(spun.on_ground(&field));
+
+        let spawn = Piece { kind: PieceType::T, rotation: RotationState::North, x: 4, y: 20 };
+        let positions = reachable(spawn, &field, KickTable::Srs);
+        assert!(positions.contains(&spun));
+        // the slot can't be entered in any other rotation
+        let west = Piece { rotation: RotationState::West, ..spun };
+        assert!(!positions.contains(&west));
+        let buried = Piece { y: 0, ..t };
+        assert!(reachable(buried, &field, KickTable::Srs).is_empty());
+    }
+
+    #[test]
+    fn walls() {
+        let field = [[CellColor::Empty; 10]; 23];
+        let t = Piece { kind: PieceType::T, rotation: RotationState::North, x: 1, y: 0 };
+        assert_eq!(t.shifted(-1, 0, &field), None);
+        assert_eq!(t.shifted(0, -1, &field), None);
+        assert!(t.shifted(0, 30, &field).is_some());
+    }
+}
@@ -0,0 +1,195 @@
+//! Decoding and encoding fumen data a page at a time.
+//!
+//! Long replays can have thousands of pages, and often only a few of them are needed. These
+//! work on one page at a time, so a fumen never has to be held in memory all at once.
+
+use std::fmt;
+
+use crate::{
+    fumen_field_delta, push_comment, push_field, push_page_number, BASE64_CHARS, CellColor,
+    DecodeFumenError, Decoder, Fumen, Page
+};
+
+impl Fumen {
+    /// Decodes the pages of a fumen data string one at a time.
+    ///
+    /// Pages are decoded as the iterator is advanced, so stopping early skips decoding the rest
+    /// of the data. If the data is invalid, the iterator yields an error and then ends.
+    pub fn pages_iter(data: &str) -> PageIter<'_> {
+        PageIter { decoder: Decoder::new(data), failed: false }
+    }
+}
+
+/// An iterator over the pages of fumen data, created by [`Fumen::pages_iter`].
+pub struct PageIter<'a> {
+    decoder: Option<Decoder<'a>>,
+    failed: bool
+}
+
+impl Iterator for PageIter<'_> {
+    type Item = Result<Page, DecodeFumenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let decoder = match self.decoder {
+            Some(ref mut decoder) => decoder,
+            None => {
+                self.failed = true;
+                return Some(Err(DecodeFumenError));
+            }
+        };
+        if !decoder.has_more() {
+            return None;
+        }
+        match decoder.next_page() {
+            Some(decoded) => Some(Ok(decoded.page)),
+            None => {
+                self.failed = true;
+                Some(Err(DecodeFumenError))
+            }
+        }
+    }
+}
+
+/// Writes fumen data one page at a time.
+///
+/// The output is the same as [`Fumen::encode`] for a fumen with the same pages. Pages with an
+/// unchanged field are written together once the sequence of them ends, since the length of the
+/// sequence comes first, so up to 64 pages can be held back until the next page or
+/// [`Encoder::finish`].
+pub struct Encoder<W> {
+    out: W,
+    prev_field: [[CellColor; 10]; 24],
+    first: bool,
+    /// the number of pages after the first in the current unchanged field sequence, and the
+    /// data of its pages
+    empty_fields: Option<(usize, String)>,
+    buffer: String
+}
+
+impl<W: fmt::Write> Encoder<W> {
+    /// Starts writing fumen data to `out`.
+    pub fn new(mut out: W) -> Result<Encoder<W>, fmt::Error> {
+        out.write_str("v115@")?;
+        Ok(Encoder {
+            out,
+            prev_field: [[CellColor::Empty; 10]; 24],
+            first: true,
+            empty_fields: None,
+            buffer: String::new()
+        })
+    }
+
+    pub fn push(&mut self, page: &Page) -> fmt::Result {
+        let deltas = fumen_field_delta(self.prev_field, page.fumen_field());
+        let first = std::mem::replace(&mut self.first, false);
+        self.prev_field = page.next_page().fumen_field();
+        if deltas == [[8; 10]; 24] {
+            let (count, data) = self.empty_fields.get_or_insert_with(|| (0, String::new()));
+            if !data.is_empty() {
+                *count += 1;
+            }
+            push_page_number(data, page, first);
+            if let Some(ref comment) = page.comment {
+                push_comment(data, comment);
+            }
+            if *count == 63 {
+                self.finish_empty_fields()?;
+            }
+            Ok(())
+        } else {
+            self.finish_empty_fields()?;
+            self.buffer.clear();
+            push_field(&mut self.buffer, &deltas);
+            push_page_number(&mut self.buffer, page, first);
+            if let Some(ref comment) = page.comment {
+                push_comment(&mut self.buffer, comment);
+            }
+            self.out.write_str(&self.buffer)
+        }
+    }
+
+    /// Writes any pages that are held back, returning the output.
+    pub fn finish(mut self) -> Result<W, fmt::Error> {
+        self.finish_empty_fields()?;
+        Ok(self.out)
+    }
+
+    fn finish_empty_fields(&mut self) -> fmt::Result {
+        if let Some((count, data)) = self.empty_fields.take() {
+            self.out.write_str("vh")?;
+            self.out.write_char(BASE64_CHARS[count] as char)?;
+            self.out.write_str(&data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::stream::*;
+
+    fn encode_streaming(fumen: &Fumen) -> String {
+        let mut encoder = Encoder::new(String::new()).unwrap();
+        for page in fumen.get_pages() {
+            encoder.push(page).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn pages_iter() {
+        let mut fumen = Fumen::default();
+        for i in 0..100 {
+            fumen.add_page();
+            let page = fumen.get_pages_mut().last_mut().unwrap();
+            if i % 10 == 0 {
+                page.field[0][i / 10] = CellColor::Grey;
+            }
+            if i == 7 {
+                page.set_comment(Some("hello".to_owned()));
+            }
+        }
+        let data = fumen.encode();
+        let pages: Result<Vec<_>, _> = Fumen::pages_iter(&data).collect();
+        assert_eq!(&pages.unwrap(), fumen.get_pages());
+        assert_eq!(Fumen::pages_iter(&data).nth(7).unwrap().unwrap().comment(),
+            Some("hello".to_owned()));
+
+        let truncated = &data[..data.len() - 1];
+        let results: Vec<_> = Fumen::pages_iter(truncated).collect();
+        assert_eq!(results.len(), 100);
+        assert!(results[..99].iter().all(Result::is_ok));
+        assert_eq!(results[99], Err(DecodeFumenError));
+        let results: Vec<_> = Fumen::pages_iter("v114@vhAAgH").collect();
+        assert_eq!(results, vec![Err(DecodeFumenError)]);
+        assert_eq!(Fumen::pages_iter("v115@").count(), 0);
+    }
+
+    #[test]
+    fn encoder() {
+        let mut fumen = Fumen::default();
+        assert_eq!(encode_streaming(&fumen), fumen.encode());
+        for i in 0..150 {
+            fumen.add_page();
+            let page = fumen.get_pages_mut().last_mut().unwrap();
+            if i % 70 == 5 {
+                page.field[1][i % 10] = CellColor::T;
+                page.set_comment(Some(format!("page {}", i)));
+            }
+            if i == 100 {
+                page.piece = Some(Piece {
+                    kind: PieceType::I,
+                    rotation: RotationState::North,
+                    x: 4,
+                    y: 0
+                });
+            }
+        }
+        assert!(fumen.set_guideline(false));
+        assert_eq!(encode_streaming(&fumen), fumen.encode());
+    }
+}
@@ -0,0 +1,312 @@
+//! Import of TETR.IO replays.
+//!
+//! A `.ttr` file holds a single game, and a `.ttrm` file holds every round of a match with a game
+//! for each player. Games are played again from their key events, frame by frame, using the
+//! handling, gravity and lock delay in the replay's options, so the result is only as accurate
+//! as that simulation. Garbage is received after the next piece that doesn't clear lines, without
+//! cancelling it against garbage sent, and only the 7-bag randomizer is supported.
+
+use serde_json::Value;
+
+use crate::randomizer::TetrioBag;
+use crate::replay::{Game, GarbageDisplay, ParseReplayError, Settings};
+use crate::srs::{KickTable, Rotation};
+use crate::Fumen;
+
+/// Soft drop factors at least this large drop the piece instantly.
+const INSTANT_SOFT_DROP: f64 = 41.0;
+
+impl Fumen {
+    /// Plays each game in a TETR.IO replay, returning a fumen for each.
+    ///
+    /// Games are in the order they appear in the replay, which is round by round for matches.
+    pub fn from_tetrio_replay(
+        json: &str, garbage: GarbageDisplay
+    ) -> Result<Vec<Fumen>, ParseReplayError> {
+        let replay: Value = serde_json::from_str(json).map_err(|_| ParseReplayError)?;
+        let games = games(&replay);
+        if games.is_empty() {
+            return Err(ParseReplayError);
+        }
+        games.into_iter().map(|game| play(game, garbage)).collect()
+    }
+}
+
+/// Finds the games in both the older and newer replay layouts.
+fn games(replay: &Value) -> Vec<&Value> {
+    let mut games = vec![];
+    match &replay["data"] {
+        Value::Array(rounds) => for round in rounds {
+            games.extend(round["replays"].as_array().into_iter().flatten());
+        }
+        data if data["events"].is_array() => games.push(data),
+        _ => {}
+    }
+    match &replay["replay"]["rounds"] {
+        Value::Array(rounds) => for round in rounds {
+            games.extend(round.as_array().into_iter().flatten().map(|game| &game["replay"]));
+        }
+        _ if replay["replay"]["events"].is_array() => games.push(&replay["replay"]),
+        _ => {}
+    }
+    games
+}
+
+fn play(game: &Value, garbage: GarbageDisplay) -> Result<Fumen, ParseReplayError> {
+    let events = game["events"].as_array().ok_or(ParseReplayError)?;
+    let options = events.iter()
+        .find(|e| e["type"] == "full")
+        .map(|e| &e["data"]["options"])
+        .filter(|options| options.is_object())
+        .unwrap_or(&game["options"]);
+    let seed = options["seed"].as_f64().ok_or(ParseReplayError)?;
+    if options["bagtype"].as_str().is_some_and(|bag| bag != "7-bag") {
+        return Err(ParseReplayError);
+    }
+    let handling = &options["handling"];
+    let settings = Settings {
+        kicks: match options["kickset"].as_str() {
+            Some("SRS") => KickTable::Srs,
+            _ => KickTable::SrsPlus
+        },
+        previews: options["nextcount"].as_u64().unwrap_or(5) as usize,
+        garbage
+    };
+
+    let mut player = Player {
+        game: Game::new(settings, TetrioBag::new(seed as u32)),
+        das: handling["das"].as_f64().unwrap_or(10.0),
+        arr: handling["arr"].as_f64().unwrap_or(2.0),
+        sdf: handling["sdf"].as_f64().unwrap_or(6.0),
+        gravity: options["g"].as_f64().unwrap_or(0.02),
+        lock_delay: options["locktime"].as_f64().unwrap_or(30.0),
+        lock_resets: options["lockresets"].as_u64().unwrap_or(15),
+        frame: 0,
+        left: false,
+        right: false,
+        direction: 0,
+        charge: 0.0,
+        repeat: 0.0,
+        soft_drop: false,
+        fall: 0.0,
+        lock_timer: 0.0,
+        resets: 0
+    };
+
+    for event in events {
+        let frame = event["frame"].as_u64().ok_or(ParseReplayError)?;
+        while player.frame < frame && !player.game.ended() {
+            player.tick();
+        }
+        let key = event["data"]["key"].as_str();
+        match (event["type"].as_str(), key) {
+            (Some("keydown"), Some(key)) => player.press(key),
+            (Some("keyup"), Some(key)) => player.release(key),
+            (Some("ige"), _) => if let Some((lines, column)) = find_garbage(&event["data"]) {
+                player.game.add_garbage(lines, column);
+            }
+            (Some("end"), _) => break,
+            _ => {}
+        }
+    }
+    Ok(player.game.fumen())
+}
+
+/// Finds the garbage in an in-game event, which is nested differently between versions.
+fn find_garbage(data: &Value) -> Option<(usize, usize)> {
+    match data {
+        // sent to the attacker to confirm the garbage was received
+        Value::Object(_) if data["type"] == "interaction_confirm" => None,
+        Value::Object(_) if data["type"] == "garbage" => {
+            let lines = data["amt"].as_u64()? as usize;
+            let column = data["column"].as_u64().unwrap_or(0) as usize;
+            Some((lines, column))
+        }
+        Value::Object(map) => map.values().find_map(find_garbage),
+        _ => None
+    }
+}
+
+/// The state of the player's inputs, and the timers that depend on them.
+struct Player {
+    game: Game<TetrioBag>,
+    das: f64,
+    arr: f64,
+    sdf: f64,
+    gravity: f64,
+    lock_delay: f64,
+    lock_resets: u64,
+
+    frame: u64,
+    left: bool,
+    right: bool,
+    /// The direction being auto-shifted, which is the last held direction pressed.
+    direction: i32,
+    charge: f64,
+    repeat: f64,
+    soft_drop: bool,
+    fall: f64,
+    lock_timer: f64,
+    resets: u64
+}
+
+impl Player {
+    fn press(&mut self, key: &str) {
+        match key {
+            "moveLeft" => {
+                self.left = true;
+                self.start_shift(-1);
+            }
+            "moveRight" => {
+                self.right = true;
+                self.start_shift(1);
+            }
+            "softDrop" => self.soft_drop = true,
+            "rotateCW" => self.moved(|game| game.rotate(Rotation::Clockwise)),
+            "rotateCCW" => self.moved(|game| game.rotate(Rotation::CounterClockwise)),
+            "rotate180" => self.moved(|game| game.rotate(Rotation::Half)),
+            "hardDrop" => {
+                self.game.hard_drop();
+                self.new_piece();
+            }
+            "hold" if self.game.hold() => self.new_piece(),
+            _ => {}
+        }
+    }
+
+    fn release(&mut self, key: &str) {
+        match key {
+            "moveLeft" => {
+                self.left = false;
+                if self.direction == -1 {
+                    self.direction = if self.right { 1 } else { 0 };
+                    self.charge = 0.0;
+                }
+            }
+            "moveRight" => {
+                self.right = false;
+                if self.direction == 1 {
+                    self.direction = if self.left { -1 } else { 0 };
+                    self.charge = 0.0;
+                }
+            }
+            "softDrop" => self.soft_drop = false,
+            _ => {}
+        }
+    }
+
+    fn start_shift(&mut self, direction: i32) {
+        self.direction = direction;
+        self.charge = 0.0;
+        self.repeat = 0.0;
+        self.moved(|game| game.shift(direction));
+    }
+
+    /// Runs a move, resetting lock delay if it succeeds.
+    fn moved(&mut self, f: impl FnOnce(&mut Game<TetrioBag>) -> bool) {
+        if f(&mut self.game) && self.lock_timer > 0.0 && self.resets < self.lock_resets {
+            self.lock_timer = 0.0;
+            self.resets += 1;
+        }
+    }
+
+    fn new_piece(&mut self) {
+        self.fall = 0.0;
+        self.lock_timer = 0.0;
+        self.resets = 0;
+    }
+
+    /// Advances the game by one frame.
+    fn tick(&mut self) {
+        self.frame += 1;
+
+        if self.direction != 0 {
+            let direction = self.direction;
+            self.charge += 1.0;
+            if self.charge >= self.das {
+                if self.arr <= 0.0 {
+                    self.moved(|game| game.shift_fully(direction));
+                } else {
+                    self.repeat += 1.0;
+                    while self.repeat >= self.arr {
+                        self.repeat -= self.arr;
+                        self.moved(|game| game.shift(direction));
+                    }
+                }
+            }
+        }
+
+        if self.soft_drop && self.sdf >= INSTANT_SOFT_DROP {
+            self.game.sonic_drop();
+        } else {
+            self.fall += if self.soft_drop { self.gravity * self.sdf } else { self.gravity };
+            let cells = self.fall.floor();
+            self.fall -= cells;
+            if self.game.fall(cells as u32) {
+                self.lock_timer = 0.0;
+            }
+        }
+
+        if self.game.on_ground() {
+            self.fall = 0.0;
+            self.lock_timer += 1.0;
+            if self.lock_timer >= self.lock_delay {
+                self.game.lock();
+                self.new_piece();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::quiz::Quiz;
+    use crate::replay::*;
+    use crate::tetrio::*;
+
+    #[test]
+    fn replay() {
+        let json = r#"{"data": {"events": [
+            {"frame": 0, "type": "start", "data": {}},
+            {"frame": 0, "type": "full", "data": {"options": {
+                "seed": 42, "g": 0, "handling": {"das": 6, "arr": 0, "sdf": 41}
+            }}},
+            {"frame": 10, "type": "keydown", "data": {"key": "moveLeft"}},
+            {"frame": 11, "type": "keyup", "data": {"key": "moveLeft"}},
+            {"frame": 12, "type": "keydown", "data": {"key": "hardDrop"}},
+            {"frame": 13, "type": "keyup", "data": {"key": "hardDrop"}},
+            {"frame": 14, "type": "ige", "data": {
+                "id": 0, "frame": 14, "type": "ige", "data": {
+                    "type": "interaction",
+                    "data": {"type": "garbage", "amt": 1, "x": 0, "y": 0, "column": 9},
+                    "sender": "opponent", "sent_frame": 12, "cid": 1
+                }
+            }},
+            {"frame": 20, "type": "keydown", "data": {"key": "moveRight"}},
+            {"frame": 40, "type": "keydown", "data": {"key": "hardDrop"}},
+            {"frame": 41, "type": "keyup", "data": {"key": "hardDrop"}},
+            {"frame": 42, "type": "end", "data": {}}
+        ]}}"#;
+        let fumens = Fumen::from_tetrio_replay(json, GarbageDisplay::Rise).unwrap();
+        assert_eq!(fumens.len(), 1);
+        let pages = fumens[0].get_pages();
+        assert_eq!(pages.len(), 2);
+
+        let queue: Vec<_> = TetrioBag::new(42).take(7).collect();
+        let quiz = Quiz::parse(&pages[0].comment().unwrap()).unwrap();
+        assert_eq!(quiz.current, Some(queue[0]));
+        assert_eq!(quiz.next, &queue[1..6]);
+
+        // one cell left, then auto-shifted all the way to the right
+        let first = pages[0].piece.unwrap();
+        assert_eq!((first.kind, first.x), (queue[0], 3));
+        let second = pages[1].piece.unwrap();
+        assert_eq!(second.kind, queue[1]);
+        assert!(second.cells().iter().any(|&(x, _)| x == 9));
+        assert!(pages[1].rise);
+        assert_eq!(pages[1].get_garbage_row()[9], CellColor::Empty);
+
+        assert_eq!(Fumen::from_tetrio_replay("{}", GarbageDisplay::Rise), Err(ParseReplayError));
+    }
+}
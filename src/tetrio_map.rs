@@ -0,0 +1,182 @@
+//! Conversion to and from TETR.IO custom map strings.
+//!
+//! A map string is `board?queue?hold`. The board is rows of 10 cells from top to bottom, with
+//! `_` for empty cells, piece letters for coloured cells, and `#` (or `@` for dark garbage) for
+//! garbage. The queue and hold are written as piece letters, and are optional when parsing.
+//!
+//! The board strings of Puyo Puyo Tetris mods are not supported, since there is no description
+//! of their format to check an implementation against.
+
+use crate::quiz::Quiz;
+use crate::{CellColor, Fumen, Page, PieceType};
+
+/// The height of TETR.IO's board, including the rows above the visible field.
+const MAP_HEIGHT: usize = 40;
+
+impl Page {
+    /// Writes the page as a TETR.IO custom map string.
+    ///
+    /// A garbage row that isn't empty is included as if it had already risen into the field,
+    /// and the queue and hold come from the page's quiz comment, with the current piece first in
+    /// the queue. Pages that only inherit a quiz comment from earlier pages have no queue.
+    /// Cells keep their colour; see [`Fumen::to_tetrio_map`] for pages shown in monochrome.
+    pub fn to_tetrio_map(&self) -> String {
+        let mut rows: Vec<[CellColor; 10]> = vec![];
+        if self.garbage_row != [CellColor::Empty; 10] {
+            rows.push(self.garbage_row);
+        }
+        rows.extend_from_slice(&self.field);
+        rows.resize(MAP_HEIGHT, [CellColor::Empty; 10]);
+
+        let mut map = String::new();
+        for row in rows.iter().rev() {
+            map.extend(row.iter().map(|&cell| match cell {
+                CellColor::Empty => '_',
+                CellColor::I => 'i',
+                CellColor::L => 'l',
+                CellColor::O => 'o',
+                CellColor::Z => 'z',
+                CellColor::T => 't',
+                CellColor::J => 'j',
+                CellColor::S => 's',
+                CellColor::Grey => '#'
+            }));
+        }
+
+        let quiz = self.comment.as_deref().and_then(Quiz::parse).unwrap_or_default();
+        map.push('?');
+        map.extend(quiz.current.iter().chain(&quiz.next).map(|p| p.to_char().to_ascii_lowercase()));
+        map.push('?');
+        map.extend(quiz.hold.map(|p| p.to_char().to_ascii_lowercase()));
+        map
+    }
+
+    /// Parses a TETR.IO custom map string.
+    ///
+    /// The board is read from the bottom up, and anything above the 23 rows fumen can show must
+    /// be empty. If there is a queue or hold, it becomes the page's quiz comment.
+    ///
+    /// Map strings have no garbage row, so the page's garbage row is always empty. A page written
+    /// with [`Page::to_tetrio_map`] that had a garbage row comes back with that row risen into
+    /// the bottom of its field instead.
+    pub fn from_tetrio_map(map: &str) -> Result<Page, ParseTetrioMapError> {
+        let mut sections = map.trim().split('?');
+        let board: Vec<char> = sections.next().unwrap_or("").chars().collect();
+        let queue = parse_pieces(sections.next().unwrap_or(""))?;
+        let hold = parse_pieces(sections.next().unwrap_or(""))?;
+        let rows = board.rchunks_exact(10);
+        if sections.next().is_some() || hold.len() > 1 || !rows.remainder().is_empty() {
+            return Err(ParseTetrioMapError);
+        }
+
+        let mut page = Page::default();
+        for (y, row) in rows.enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                let cell = match c {
+                    '_' => CellColor::Empty,
+                    '#' | '@' => CellColor::Grey,
+                    c => PieceType::from_char(c.to_ascii_uppercase())
+                        .filter(|_| c.is_ascii_lowercase())
+                        .ok_or(ParseTetrioMapError)?
+                        .into()
+                };
+                match page.field.get_mut(y) {
+                    Some(row) => row[x] = cell,
+                    None if cell == CellColor::Empty => {}
+                    None => return Err(ParseTetrioMapError)
+                }
+            }
+        }
+
+        if !queue.is_empty() || !hold.is_empty() {
+            let quiz = Quiz {
+                hold: hold.first().copied(),
+                current: queue.first().copied(),
+                next: queue.iter().skip(1).copied().collect()
+            };
+            page.comment = Some(quiz.to_string());
+        }
+        Ok(page)
+    }
+}
+
+impl Fumen {
+    /// Writes the page at `index` as a TETR.IO custom map string, as [`Page::to_tetrio_map`]
+    /// does, with every filled cell written as garbage if the page is shown in monochrome.
+    pub fn to_tetrio_map(&self, index: usize) -> Option<String> {
+        Some(self.page_as_shown(index)?.to_tetrio_map())
+    }
+}
+
+fn parse_pieces(s: &str) -> Result<Vec<PieceType>, ParseTetrioMapError> {
+    s.chars()
+        .map(|c| PieceType::from_char(c.to_ascii_uppercase()).ok_or(ParseTetrioMapError))
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct ParseTetrioMapError;
+
+impl std::fmt::Display for ParseTetrioMapError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(fmt, "the string is not a valid TETR.IO map")
+    }
+}
+
+impl std::error::Error for ParseTetrioMapError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::tetrio_map::*;
+
+    #[test]
+    fn round_trip() {
+        let mut page = Page::default();
+        page.field[0] = [CellColor::Grey; 10];
+        page.field[0][3] = CellColor::Empty;
+        page.field[1][0] = CellColor::T;
+        page.comment = Some("#Q=[I](T)SZ".to_owned());
+        let map = page.to_tetrio_map();
+        assert_eq!(map.len(), 400 + 6);
+        assert!(map.ends_with("t_________###_######?tsz?i"));
+        assert_eq!(Page::from_tetrio_map(&map), Ok(page));
+    }
+
+    #[test]
+    fn monochrome() {
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).field[0][0] = CellColor::T;
+        }
+        assert!(fumen.to_tetrio_map(0).unwrap().ends_with("t_________??"));
+        assert!(fumen.set_guideline(false));
+        assert!(fumen.to_tetrio_map(0).unwrap().ends_with("#_________??"));
+        assert_eq!(fumen.to_tetrio_map(1), None);
+    }
+
+    #[test]
+    fn garbage_row_and_errors() {
+        let mut garbage_row = [CellColor::Grey; 10];
+        garbage_row[9] = CellColor::Empty;
+        let page = Page { garbage_row, ..Page::default() };
+        let map = page.to_tetrio_map();
+        assert!(map.ends_with("#########_??"));
+        let parsed = Page::from_tetrio_map(&map).unwrap();
+        // the garbage row is lost, as it has risen into the field
+        assert_ne!(parsed, page);
+        assert_eq!(parsed.field[0], page.garbage_row);
+        assert_eq!(parsed.field[1], [CellColor::Empty; 10]);
+        assert_eq!(parsed.garbage_row, [CellColor::Empty; 10]);
+        assert_eq!(parsed.comment(), None);
+
+        assert_eq!(Page::from_tetrio_map("@@@@@@@@@_"), Ok(parsed));
+        assert_eq!(Page::from_tetrio_map("_________"), Err(ParseTetrioMapError));
+        assert_eq!(Page::from_tetrio_map("_________X"), Err(ParseTetrioMapError));
+        assert_eq!(Page::from_tetrio_map("?ix"), Err(ParseTetrioMapError));
+        assert_eq!(Page::from_tetrio_map("?i?oo"), Err(ParseTetrioMapError));
+        let tall = format!("#_________{}", "_".repeat(230));
+        assert_eq!(Page::from_tetrio_map(&tall), Err(ParseTetrioMapError));
+    }
+}
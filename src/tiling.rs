@@ -0,0 +1,186 @@
+//! Enumerating the ways to fill part of a field with tetrominoes.
+
+use crate::infer::push_placements;
+use crate::{CellColor, Fumen, Page, Piece, PieceType, RotationState};
+
+pub(crate) const KINDS: [PieceType; 7] = [
+    PieceType::I, PieceType::L, PieceType::O, PieceType::Z, PieceType::T, PieceType::J, PieceType::S
+];
+
+pub(crate) const ROTATIONS: [RotationState; 4] = [
+    RotationState::North, RotationState::East, RotationState::South, RotationState::West
+];
+
+/// Finds every way to cover exactly the cells set in `region` with tetrominoes.
+///
+/// If `kinds` is given, each tiling uses at most as many pieces of each type as `kinds` has,
+/// otherwise any pieces can be used. Each tiling is listed once, with its pieces in the order of
+/// their lowest, leftmost cell, and pieces with the same cells in more than one rotation are
+/// given the first rotation out of North, East, South and West.
+pub fn tilings(region: &[[bool; 10]; 23], kinds: Option<&[PieceType]>) -> Vec<Vec<Piece>> {
+    let mut counts = match kinds {
+        Some(kinds) => {
+            let mut counts = [0; 8];
+            for &kind in kinds {
+                counts[kind as usize] += 1;
+            }
+            counts
+        }
+        None => [usize::MAX; 8]
+    };
+    let mut region = *region;
+    let mut results = vec![];
+    if region.iter().flatten().filter(|&&cell| cell).count() % 4 == 0 {
+        search(&mut region, &mut counts, &mut vec![], &mut results);
+    }
+    results
+}
+
+impl Page {
+    /// Finds every way to fill the empty cells of the bottom `height` rows with tetrominoes.
+    ///
+    /// Each tiling is a fumen with a page placing each piece in the order [`tilings`] gives
+    /// them, starting from this page without its piece, and a last page showing the result.
+    /// Pieces placed after lines are cleared are moved down with the rest of the field. See
+    /// [`tilings`] for how `kinds` restricts the pieces.
+    pub fn tilings(&self, height: usize, kinds: Option<&[PieceType]>) -> Vec<Fumen> {
+        let mut region = [[false; 10]; 23];
+        for (region, row) in region.iter_mut().zip(&self.field).take(height) {
+            *region = row.map(|cell| cell == CellColor::Empty);
+        }
+        let start = Page { piece: None, ..self.clone() };
+        tilings(&region, kinds).into_iter().map(|pieces| {
+            let mut pages = vec![];
+            push_placements(&mut pages, start.clone(), &pieces);
+            let last = pages.last().map_or_else(|| start.clone(), Page::next_page);
+            pages.push(last);
+            Fumen { pages, source: None }
+        }).collect()
+    }
+}
+
+/// Covers the rest of `region` in every way, starting with its lowest, leftmost cell.
+fn search(
+    region: &mut [[bool; 10]; 23],
+    counts: &mut [usize; 8],
+    pieces: &mut Vec<Piece>,
+    results: &mut Vec<Vec<Piece>>
+) {
+    let first = (0..23)
+        .flat_map(|y| (0..10).map(move |x| (x, y)))
+        .find(|&(x, y)| region[y][x]);
+    let (x, y) = match first {
+        Some((x, y)) => (x as i32, y as i32),
+        None => {
+            results.push(pieces.clone());
+            return;
+        }
+    };
+
+    for &kind in &KINDS {
+        if counts[kind as usize] == 0 {
+            continue;
+        }
+        // placements with the same cells as one already tried would give the same tilings again
+        let mut tried = vec![];
+        for &rotation in &ROTATIONS {
+            for &(dx, dy) in &kind.offsets(rotation) {
+                let (px, py) = (x - dx, y - dy);
+                if px < 0 || py < 0 {
+                    continue;
+                }
+                let piece = Piece { kind, rotation, x: px as u32, y: py as u32 };
+                if !piece.in_field() {
+                    continue;
+                }
+                let mut cells = piece.cells();
+                if !cells.iter().all(|&(x, y)| region[y as usize][x as usize]) {
+                    continue;
+                }
+                cells.sort_unstable();
+                if tried.contains(&cells) {
+                    continue;
+                }
+                tried.push(cells);
+
+                for &(x, y) in &cells {
+                    region[y as usize][x as usize] = false;
+                }
+                counts[kind as usize] -= 1;
+                pieces.push(piece);
+                search(region, counts, pieces, results);
+                pieces.pop();
+                counts[kind as usize] += 1;
+                for &(x, y) in &cells {
+                    region[y as usize][x as usize] = true;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use crate::tiling::*;
+
+    /// A page with only the leftmost `width` columns of the bottom `height` rows empty.
+    fn well(width: usize, height: usize) -> Page {
+        let mut page = Page::default();
+        for row in &mut page.field[..height] {
+            *row = [CellColor::Grey; 10];
+            for cell in &mut row[..width] {
+                *cell = CellColor::Empty;
+            }
+        }
+        page
+    }
+
+    #[test]
+    fn page_tilings() {
+        use PieceType::*;
+        let page = well(4, 2);
+        let all = page.tilings(2, None);
+        // two Is, two Os, two Ls or two Js
+        assert_eq!(all.len(), 4);
+        for fumen in &all {
+            assert_eq!(fumen.pages.len(), 3);
+            assert!(fumen.pages[..2].iter().all(|page| page.piece.is_some() && page.lock));
+            assert!(fumen.check_continuity().is_empty());
+            // both rows are cleared
+            assert_eq!(fumen.pages[2].field, [[CellColor::Empty; 10]; 23]);
+        }
+        let kinds: Vec<_> = all.iter().map(|fumen| fumen.pages[0].piece.unwrap().kind).collect();
+        assert_eq!(kinds, vec![I, L, O, J]);
+        // the second I is moved down once the first clears the bottom line
+        let ys: Vec<_> = all[0].pages[..2].iter().map(|page| page.piece.unwrap().y).collect();
+        assert_eq!(ys, vec![0, 0]);
+        assert_eq!(page.tilings(2, Some(&[O, O])).len(), 1);
+        assert_eq!(page.tilings(2, Some(&[O, I, I, T])).len(), 1);
+        assert_eq!(page.tilings(2, Some(&[O, I, T])).len(), 0);
+        assert_eq!(page.tilings(2, Some(&[L, J])).len(), 0);
+        assert_eq!(page.tilings(2, Some(&[L, L, J])).len(), 1);
+        assert_eq!(well(3, 2).tilings(2, None).len(), 0);
+
+        let pieces = tilings(&[[false; 10]; 23], None);
+        assert_eq!(pieces, vec![vec![]]);
+    }
+
+    #[test]
+    fn perfect_clear() {
+        use PieceType::*;
+        let tilings = well(4, 4).tilings(4, Some(&[I, O, T, T, L, J, S, Z]));
+        assert!(!tilings.is_empty());
+        let mut placements: Vec<Vec<_>> = tilings.iter()
+            .map(|fumen| fumen.pages.iter().map(|page| format!("{:?}", page.piece)).collect())
+            .collect();
+        placements.sort();
+        placements.dedup();
+        assert_eq!(placements.len(), tilings.len());
+        for fumen in &tilings {
+            assert_eq!(fumen.pages.len(), 5);
+            assert!(fumen.check_continuity().is_empty());
+            assert_eq!(fumen.pages[4].field, [[CellColor::Empty; 10]; 23]);
+        }
+    }
+}
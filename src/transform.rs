@@ -0,0 +1,274 @@
+//! Mirroring, shifting and recolouring whole fumens.
+
+use wasm_bindgen::prelude::*;
+
+use crate::quiz::Quiz;
+use crate::{CellColor, Fumen, Page, Piece, PieceType, RotationState};
+
+#[wasm_bindgen]
+impl Fumen {
+    /// Mirrors every page left to right.
+    ///
+    /// L and J pieces are swapped, as are S and Z pieces, both for the pieces on each page and the
+    /// colours of the field. Quiz comments have their pieces swapped the same way, so the mirrored
+    /// fumen is the same setup built from the left instead of the right.
+    pub fn mirrored(&self) -> Fumen {
+        Fumen { pages: self.pages.iter().map(Page::mirrored).collect(), source: None }
+    }
+
+    /// Moves the field and piece of every page up by `rows` rows, or down if `rows` is negative.
+    ///
+    /// Returns `None` if a filled cell or piece would be moved out of the field, or if a page
+    /// rises a garbage row that isn't empty. Garbage rows stay below the field, so the row would
+    /// rise in below the moved field rather than with it, and the next page would no longer
+    /// follow.
+    pub fn shifted(&self, rows: i32) -> Option<Fumen> {
+        let pages = self.pages.iter().map(|page| page.shifted(rows)).collect::<Option<_>>()?;
+        Some(Fumen { pages, source: None })
+    }
+}
+
+impl Fumen {
+    /// Replaces the colour of every filled cell in the fields and garbage rows.
+    ///
+    /// Returning [`CellColor::Empty`] clears the cell. Pieces keep their colour, since it is the
+    /// colour of the piece type, so `fumen.map_colors(|_| CellColor::Grey)` greys out everything
+    /// but the piece on each page.
+    pub fn map_colors(&self, mut f: impl FnMut(CellColor) -> CellColor) -> Fumen {
+        Fumen {
+            pages: self.pages.iter().map(|page| page.map_colors(&mut f)).collect(),
+            source: None
+        }
+    }
+
+    /// The page at `index` in the colours it is shown in, with every filled cell grey if the
+    /// page is shown in monochrome.
+    pub(crate) fn page_as_shown(&self, index: usize) -> Option<Page> {
+        let page = self.pages.get(index)?;
+        if self.guideline_at(index) {
+            Some(page.clone())
+        } else {
+            Some(page.map_colors(|_| CellColor::Grey))
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl Page {
+    /// Mirrors the page left to right. See [`Fumen::mirrored`].
+    pub fn mirrored(&self) -> Page {
+        let mirror_row = |row: &[CellColor; 10]| {
+            let mut row = *row;
+            row.reverse();
+            row.map(CellColor::mirrored)
+        };
+        let mut field = self.field;
+        for row in &mut field {
+            *row = mirror_row(row);
+        }
+        Page {
+            piece: self.piece.map(Piece::mirrored),
+            comment: self.comment.as_deref().map(mirror_comment),
+            field,
+            garbage_row: mirror_row(&self.garbage_row),
+            ..self.clone()
+        }
+    }
+
+    /// Moves the field and piece up by `rows` rows. See [`Fumen::shifted`].
+    pub fn shifted(&self, rows: i32) -> Option<Page> {
+        if rows != 0 && self.rise && self.garbage_row != [CellColor::Empty; 10] {
+            return None;
+        }
+        let piece = match self.piece {
+            Some(piece) => {
+                let y = piece.y as i32 + rows;
+                let piece = Piece { y: y.max(0) as u32, ..piece };
+                if y < 0 || !piece.in_field() {
+                    return None;
+                }
+                Some(piece)
+            }
+            None => None
+        };
+        let mut field = [[CellColor::Empty; 10]; 23];
+        for (y, row) in self.field.iter().enumerate() {
+            let filled = row.iter().any(|&cell| cell != CellColor::Empty);
+            // rows moved below the field wrap around to indices past the end
+            match field.get_mut((y as i32 + rows) as usize) {
+                Some(new_row) => *new_row = *row,
+                _ if filled => return None,
+                _ => {}
+            }
+        }
+        Some(Page { piece, field, ..self.clone() })
+    }
+}
+
+impl Page {
+    /// Replaces the colour of every filled cell. See [`Fumen::map_colors`].
+    pub fn map_colors(&self, mut f: impl FnMut(CellColor) -> CellColor) -> Page {
+        let mut page = self.clone();
+        let rows = page.field.iter_mut().chain(std::iter::once(&mut page.garbage_row));
+        for cell in rows.flatten() {
+            if *cell != CellColor::Empty {
+                *cell = f(*cell);
+            }
+        }
+        page
+    }
+}
+
+impl Piece {
+    /// The piece mirrored left to right in the field, with L and J and S and Z swapped.
+    pub fn mirrored(self) -> Piece {
+        let rotation = match self.rotation {
+            RotationState::East => RotationState::West,
+            RotationState::West => RotationState::East,
+            rotation => rotation
+        };
+        // true rotation centres aren't symmetric for every piece, so the position comes from
+        // lining the cells up with the mirrored cells
+        let target = self.cells();
+        let target_x = target.iter().map(|&(x, _)| 9 - x).min().unwrap();
+        let target_y = target.iter().map(|&(_, y)| y).min().unwrap();
+        let kind = self.kind.mirrored();
+        let offsets = kind.offsets(rotation);
+        let x = offsets.iter().map(|&(x, _)| x).min().unwrap();
+        let y = offsets.iter().map(|&(_, y)| y).min().unwrap();
+        Piece { kind, rotation, x: (target_x - x) as u32, y: (target_y - y) as u32 }
+    }
+}
+
+impl PieceType {
+    /// The piece with the mirrored shape: L and J are swapped, as are S and Z.
+    pub fn mirrored(self) -> PieceType {
+        match self {
+            PieceType::L => PieceType::J,
+            PieceType::J => PieceType::L,
+            PieceType::S => PieceType::Z,
+            PieceType::Z => PieceType::S,
+            kind => kind
+        }
+    }
+}
+
+impl CellColor {
+    /// The colour of the mirrored piece. See [`PieceType::mirrored`].
+    pub fn mirrored(self) -> CellColor {
+        match self {
+            CellColor::L => CellColor::J,
+            CellColor::J => CellColor::L,
+            CellColor::S => CellColor::Z,
+            CellColor::Z => CellColor::S,
+            color => color
+        }
+    }
+}
+
+/// Swaps the pieces of a quiz comment, keeping anything after the queue.
+fn mirror_comment(comment: &str) -> String {
+    match Quiz::parse(comment) {
+        Some(quiz) => {
+            let quiz = Quiz {
+                hold: quiz.hold.map(PieceType::mirrored),
+                current: quiz.current.map(PieceType::mirrored),
+                next: quiz.next.into_iter().map(PieceType::mirrored).collect()
+            };
+            let rest = comment.find(';').map_or("", |i| &comment[i..]);
+            format!("{}{}", quiz, rest)
+        }
+        None => comment.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn mirrored() {
+        use PieceType::*;
+        let rotations = [
+            RotationState::North, RotationState::East, RotationState::South, RotationState::West
+        ];
+        for &kind in &[I, L, O, Z, T, J, S] {
+            for &rotation in &rotations {
+                let piece = Piece { kind, rotation, x: 4, y: 4 };
+                let mirrored = piece.mirrored();
+                let mut cells: Vec<_> = piece.cells().iter().map(|&(x, y)| (9 - x, y)).collect();
+                let mut mirrored_cells = mirrored.cells().to_vec();
+                cells.sort();
+                mirrored_cells.sort();
+                assert_eq!(cells, mirrored_cells, "{:?}", piece);
+                assert_eq!(mirrored.mirrored(), piece);
+            }
+        }
+
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0] = [CellColor::Grey; 10];
+        fumen.pages[0].field[0][0] = CellColor::Empty;
+        fumen.pages[0].field[1][0] = CellColor::L;
+        fumen.pages[0].piece = Some(Piece { kind: S, rotation: RotationState::East, x: 1, y: 2 });
+        fumen.pages[0].comment = Some("#Q=[L](S)ZT;build left".to_owned());
+        fumen.add_page();
+        let mirrored = fumen.mirrored();
+        let page = &mirrored.pages[0];
+        assert_eq!(page.field[0][9], CellColor::Empty);
+        assert_eq!(page.field[1][9], CellColor::J);
+        assert_eq!(page.piece.unwrap().kind, Z);
+        assert_eq!(page.comment(), Some("#Q=[J](Z)ST;build left".to_owned()));
+        assert_eq!(mirrored.pages[1], mirrored.pages[0].next_page());
+        assert_eq!(mirrored.mirrored(), fumen);
+    }
+
+    #[test]
+    fn shifted() {
+        let mut page = Page::default();
+        page.field[0] = [CellColor::Grey; 10];
+        page.piece = Some(Piece {
+            kind: PieceType::T,
+            rotation: RotationState::North,
+            x: 4,
+            y: 1
+        });
+        let up = page.shifted(2).unwrap();
+        assert_eq!(up.field[2], [CellColor::Grey; 10]);
+        assert_eq!(up.field[0], [CellColor::Empty; 10]);
+        assert_eq!(up.piece.unwrap().y, 3);
+        assert_eq!(up.shifted(-2), Some(page.clone()));
+        assert_eq!(page.shifted(-1), None);
+        assert_eq!(page.shifted(21), None);
+        page.field[0] = [CellColor::Empty; 10];
+        assert_eq!(page.shifted(-1).unwrap().piece.unwrap().y, 0);
+
+        // rising garbage would rise in below the shifted field
+        let mut fumen = Fumen::default();
+        let page = fumen.add_page();
+        unsafe {
+            (*page).field[0][0] = CellColor::Grey;
+            (*page).rise = true;
+        }
+        fumen.add_page();
+        assert!(fumen.shifted(1).unwrap().check_continuity().is_empty());
+        fumen.pages[0].garbage_row[0] = CellColor::Grey;
+        fumen.pages[1] = fumen.pages[0].next_page();
+        assert_eq!(fumen.shifted(1), None);
+        assert_eq!(fumen.shifted(0), Some(fumen.clone()));
+    }
+
+    #[test]
+    fn map_colors() {
+        let mut fumen = Fumen::default();
+        fumen.add_page();
+        fumen.pages[0].field[0][0] = CellColor::T;
+        fumen.pages[0].garbage_row[1] = CellColor::Grey;
+        let grey = fumen.map_colors(|_| CellColor::Grey);
+        assert_eq!(grey.pages[0].field[0][0], CellColor::Grey);
+        assert_eq!(grey.pages[0].field[0][1], CellColor::Empty);
+        let cleared = fumen.map_colors(|c| if c == CellColor::Grey { CellColor::Empty } else { c });
+        assert_eq!(cleared.pages[0].garbage_row, [CellColor::Empty; 10]);
+        assert_eq!(cleared.pages[0].field[0][0], CellColor::T);
+    }
+}